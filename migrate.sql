@@ -0,0 +1,87 @@
+--
+-- Upgrade a database created from an older pbndb.sql to the current schema. Run as postgres.
+--
+
+BEGIN;
+
+SET search_path = public, pg_catalog;
+
+ALTER TABLE scenes
+    ADD COLUMN palette integer[],
+    ADD COLUMN description text,
+    ADD COLUMN public boolean DEFAULT false NOT NULL,
+    ADD COLUMN modified bigint,
+    ADD COLUMN modifiedby text,
+    ADD COLUMN owner integer,
+    ADD COLUMN xorigin integer DEFAULT 0 NOT NULL,
+    ADD COLUMN yorigin integer DEFAULT 0 NOT NULL;
+
+-- The owner of an existing scene is taken to be the oldest permanent application with access to it.
+UPDATE scenes SET owner = (SELECT MIN(a.appid) FROM application_scene g JOIN applications a ON g.appid = a.appid
+    WHERE g.sceneid = scenes.sceneid AND NOT a.temporary);
+
+ALTER TABLE ONLY scenes
+    ADD CONSTRAINT scenes_owner_fkey FOREIGN KEY (owner) REFERENCES applications(appid) ON DELETE SET NULL;
+
+CREATE TABLE scene_cooldowns (
+    sceneid integer NOT NULL,
+    username text NOT NULL,
+    last bigint NOT NULL
+);
+
+ALTER TABLE scene_cooldowns OWNER TO postgres;
+
+ALTER TABLE ONLY scene_cooldowns
+    ADD CONSTRAINT scene_cooldowns_pkey PRIMARY KEY (sceneid, username);
+
+ALTER TABLE ONLY scene_cooldowns
+    ADD CONSTRAINT scene_cooldowns_sceneid_fkey FOREIGN KEY (sceneid) REFERENCES scenes(sceneid) ON DELETE CASCADE;
+
+REVOKE ALL ON TABLE scene_cooldowns FROM PUBLIC;
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE scene_cooldowns TO pbn;
+
+CREATE TABLE scene_protected (
+    regionid serial NOT NULL,
+    sceneid integer NOT NULL,
+    x integer NOT NULL,
+    y integer NOT NULL,
+    w integer NOT NULL,
+    h integer NOT NULL,
+    appids integer[] NOT NULL,
+    usernames text[] NOT NULL
+);
+
+ALTER TABLE scene_protected OWNER TO postgres;
+
+ALTER TABLE ONLY scene_protected
+    ADD CONSTRAINT scene_protected_pkey PRIMARY KEY (regionid);
+
+ALTER TABLE ONLY scene_protected
+    ADD CONSTRAINT scene_protected_sceneid_fkey FOREIGN KEY (sceneid) REFERENCES scenes(sceneid) ON DELETE CASCADE;
+
+REVOKE ALL ON TABLE scene_protected FROM PUBLIC;
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE scene_protected TO pbn;
+REVOKE ALL ON SEQUENCE scene_protected_regionid_seq FROM PUBLIC;
+GRANT ALL ON SEQUENCE scene_protected_regionid_seq TO pbn;
+
+CREATE INDEX scene_data_sceneid_xy ON scene_data USING btree (sceneid, x, y);
+
+CREATE VIEW scene_view AS
+ SELECT d.recordid,
+    d.sceneid,
+    d."timestamp",
+    d.username,
+    d.color,
+    (LEAST(GREATEST(((d.x)::bigint + s.xorigin), (-1)::bigint), (2147483647)::bigint))::integer AS x,
+    (LEAST(GREATEST(((d.y)::bigint + s.yorigin), (-1)::bigint), (2147483647)::bigint))::integer AS y
+   FROM (scene_data d
+     JOIN scenes s ON ((d.sceneid = s.sceneid)))
+  WHERE ((((d.x)::bigint + s.xorigin) >= 0) AND (((d.x)::bigint + s.xorigin) <= 2147483647) AND
+    (((d.y)::bigint + s.yorigin) >= 0) AND (((d.y)::bigint + s.yorigin) <= 2147483647));
+
+ALTER TABLE scene_view OWNER TO postgres;
+
+REVOKE ALL ON TABLE scene_view FROM PUBLIC;
+GRANT SELECT ON TABLE scene_view TO pbn;
+
+COMMIT;
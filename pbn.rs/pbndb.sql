@@ -0,0 +1 @@
+../pbndb.sql
\ No newline at end of file
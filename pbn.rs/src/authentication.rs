@@ -18,6 +18,10 @@ pub struct AuthenticationInfo
 
 impl AuthenticationInfo
 {
+	pub fn new(origin: Option<String>, overridden: bool, key: Option<String>) -> AuthenticationInfo
+	{
+		AuthenticationInfo{origin, overridden, key}
+	}
 	pub fn get_origin(&self, conn: &mut Connection, privileged: bool) -> Result<i32, ()>
 	{
 		//Cleanup expired suborigins.
@@ -73,11 +77,12 @@ impl<'a, 'r> FromRequest<'a, 'r> for AuthenticationInfo
 		let origin = origin.or_else(||h.get_one("api-origin").map(|x|x.to_owned()));
 		let key = h.get_one("api-key").map(|x|x.to_owned());
 		let overridden = h.contains("api-origin");
-		Outcome::Success(AuthenticationInfo{origin, overridden, key})
+		Outcome::Success(AuthenticationInfo::new(origin, overridden, key))
 	}
 }
 
 //Returns sub-origin and apikey.
+#[allow(dead_code)]
 pub fn create_local_token(conn: &mut Connection, username: &str, expiry: u64) -> (String, String)
 {
 	static BASE64URL: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
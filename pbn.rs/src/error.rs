@@ -15,18 +15,58 @@ pub enum Error
 	MethodNotSupported,
 	NotFound,
 	ConfigTooBig,
+	EventsTooBig,
+	TooManyColors,
+	ColorNotInPalette(i32),
+	BadConfig(String),
+	SceneClosed,
+	OutsideRegions,
+	ProtectedRegion,
+	Cooldown(i64),
+	NotOwner,
+	Busy,
 }
 
-trait StringTrait { fn get(self) -> String; }
-impl<'a> StringTrait for &'a str { fn get(self) -> String { self.to_owned() } }
-impl<'a> StringTrait for String { fn get(self) -> String { self } }
+//Milliseconds to whole seconds, rounding up.
+pub fn retry_after(ms: i64) -> i64
+{
+	(ms + 999) / 1000
+}
 
-fn make_response<B:StringTrait>(response: &mut Response, code: u16, statstr: &'static str, body: B)
+impl Error
 {
-	let body = body.get().into_bytes();
-	response.set_status(Status::new(code, statstr));
-	response.set_header(Header::new("Content-Type", "text/plain"));
-	response.set_sized_body(Cursor::new(body));
+	//HTTP status code, status string and message body.
+	fn describe(self) -> (u16, &'static str, String)
+	{
+		match self {
+			Error::SceneNotFound => (404, "Scene not found", format!("Scene not found\n")),
+			Error::NotFound => (404, "Not found", format!("Not found\n")),
+			Error::MethodNotSupported => (405, "Method not supported", format!("Method not supported\n")),
+			Error::InvalidOrigin => (403, "Forbidden", format!("Invalid origin\n")),
+			Error::InvalidDimensions => (422, "Invalid dimensions", format!("Invalid dimensions\n")),
+			Error::BadFormField(f) => (422, "Bad form field", format!("Bad form field: {}\n", f)),
+			Error::BadGrant => (422, "Bad grant", format!("Bad grant\n")),
+			Error::ConfigTooBig => (422, "Config too big", format!("Config too big\n")),
+			Error::EventsTooBig => (422, "Event stream too big", format!("Event stream too big\n")),
+			Error::TooManyColors => (422, "Too many colors", format!("Too many colors\n")),
+			Error::ColorNotInPalette(c) => (422, "Color not in palette", format!("Color {} not in palette\n",
+				c)),
+			Error::BadConfig(f) => (422, "Bad config", format!("Bad config: {}\n", f)),
+			Error::SceneClosed => (403, "Scene closed", format!("Scene is not open for writing\n")),
+			Error::OutsideRegions => (422, "Outside regions", format!("Cell is outside writable regions\n")),
+			Error::ProtectedRegion => (403, "Protected region", format!("Cell is in protected region\n")),
+			Error::Cooldown(ms) => (429, "Too many requests", format!("Cooldown, retry after {} second(s)\n",
+				retry_after(ms))),
+			Error::NotOwner => (403, "Forbidden", format!("Only the owner of the scene can do this\n")),
+			Error::Busy => (503, "Service unavailable", format!("Too many clients waiting for events\n")),
+			Error::BadEventStream(f) => (422, "Bad event stream", format!("Bad event stream {}\n", f)),
+		}
+	}
+	//The message, without trailing newline.
+	pub fn message(self) -> String
+	{
+		self.describe().2.trim_right().to_owned()
+	}
 }
 
 impl<'r> Responder<'r> for Error
@@ -34,25 +74,13 @@ impl<'r> Responder<'r> for Error
 	fn respond_to(self, _request: &Request) -> Result<Response<'r>, Status>
 	{
 		let mut response = Response::new();
-		match self {
-			Error::SceneNotFound => make_response(&mut response, 404, "Scene not found",
-				"Scene not found\n"),
-			Error::NotFound => make_response(&mut response, 404, "Not found",
-				"Not found\n"),
-			Error::MethodNotSupported => make_response(&mut response, 405, "Method not supported",
-				"Method not supported\n"),
-			Error::InvalidOrigin => make_response(&mut response, 403, "Forbidden", "Invalid origin\n"),
-			Error::InvalidDimensions => make_response(&mut response, 422, "Invalid dimensions",
-				"Invalid dimensions\n"),
-			Error::BadFormField(f) => make_response(&mut response, 422, "Bad form field", format!(
-				"Bad form field: {}\n", f)),
-			Error::BadGrant => make_response(&mut response, 422, "Bad grant", "Bad grant\n"),
-			Error::ConfigTooBig => make_response(&mut response, 422, "Config too big",
-				"Config too big\n"),
-			Error::BadEventStream(f) => make_response(&mut response, 422, "Bad event stream", format!(
-				"Bad event stream {}\n", f)),
+		if let Error::Cooldown(ms) = self {
+			response.set_header(Header::new("Retry-After", retry_after(ms).to_string()));
 		}
+		let (code, statstr, body) = self.describe();
+		response.set_status(Status::new(code, statstr));
+		response.set_header(Header::new("Content-Type", "text/plain"));
+		response.set_sized_body(Cursor::new(body.into_bytes()));
 		Ok(response)
 	}
 }
-
@@ -0,0 +1,112 @@
+use ::{db_connect, add_default_headers};
+use ::error::Error;
+use ::scene::Scene;
+use ::scene_endpoint::{EventInfo, GetBounds, format_row};
+use ::sceneconfig::{format_palette, scene_palette, scene_size};
+use postgres::Connection;
+use rocket::request::Request;
+use rocket::response::{Responder, Response};
+use rocket::http::{Header, Status};
+use std::cmp::min;
+use std::fmt::Write as FmtWrite;
+use std::io::Read as IoRead;
+use std::io::Error as IoError;
+
+//Number of events to fetch from the cursor at once.
+const EXPORT_BATCH: usize = 1000;
+//Size of chunks sent to client.
+const EXPORT_CHUNK: u64 = 65536;
+
+//Header line, followed by one line per event read from cursor.
+struct ExportStream
+{
+	conn: Connection,
+	buffer: Vec<u8>,
+	pos: usize,
+	ended: bool,
+}
+
+impl ExportStream
+{
+	fn refill(&mut self)
+	{
+		let mut out = String::new();
+		for row in self.conn.query(&format!("FETCH {} FROM export", EXPORT_BATCH), &[]).unwrap().iter() {
+			let ev = EventInfo {
+				ts: row.get(0),
+				username: row.get(1),
+				color: row.get(2),
+				x: row.get(3),
+				y: row.get(4),
+			};
+			format_row(&mut out, &ev);
+			out.push('\n');
+		}
+		if out.len() == 0 {
+			self.conn.execute("COMMIT", &[]).unwrap();
+			self.ended = true;
+		}
+		self.buffer = out.into_bytes();
+		self.pos = 0;
+	}
+}
+
+impl IoRead for ExportStream
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError>
+	{
+		while self.pos == self.buffer.len() {
+			if self.ended { return Ok(0); }
+			self.refill();
+		}
+		let n = min(buf.len(), self.buffer.len() - self.pos);
+		buf[..n].copy_from_slice(&self.buffer[self.pos..][..n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+pub struct SceneExport(ExportStream);
+
+impl<'r> Responder<'r> for SceneExport
+{
+	fn respond_to(self, request: &Request) -> Result<Response<'r>, Status>
+	{
+		let h = request.headers();
+		let origin = h.get_one("origin").map(|x|x.to_owned());
+
+		let mut response = Response::new();
+		response.set_status(Status::new(200, "OK"));
+		response.set_header(Header::new("Content-Type", "application/x-ndjson"));
+		if let Some(origin) = origin { if origin.starts_with("https://") {
+			response.set_header(Header::new("Access-Control-Allow-Origin", origin));
+		}}
+		add_default_headers(&mut response);
+		response.set_chunked_body(self.0, EXPORT_CHUNK);
+		Ok(response)
+	}
+}
+
+pub fn scene_get_export(scene: Scene, range: GetBounds) -> Result<SceneExport, Error>
+{
+	let conn = db_connect();
+	let (w, h) = scene_size(&conn, scene)?;
+	let tstart = range.start.unwrap_or(i64::min_value());
+	let tend = range.end.unwrap_or(i64::max_value());
+	let mut out = String::new();
+	write!(out, r#"{{"width":{},"height":{},"palette":"#, w, h).unwrap();
+	format_palette(&mut out, &scene_palette(&conn, scene));
+	out.push_str("}\n");
+	//The cursor lives until the end of transaction. Everything in the query is integer, so formatting it in is
+	//safe.
+	conn.execute("BEGIN TRANSACTION READ ONLY", &[]).unwrap();
+	conn.execute(&format!("DECLARE export NO SCROLL CURSOR FOR SELECT timestamp,username,color,x,y FROM scene_view \
+		WHERE sceneid={} AND timestamp>={} AND timestamp<={} ORDER BY timestamp, recordid", scene.as_inner(), tstart,
+		tend), &[]).unwrap();
+	Ok(SceneExport(ExportStream{
+		conn: conn,
+		buffer: out.into_bytes(),
+		pos: 0,
+		ended: false,
+	}))
+}
@@ -0,0 +1,155 @@
+use ::imagestate::ImageSource;
+use std::collections::HashMap;
+use std::io::Write;
+
+//Largest LZW code.
+const LZW_MAX_CODE: u16 = 4095;
+
+fn le16(x: usize) -> [u8;2]
+{
+	[x as u8, (x >> 8) as u8]
+}
+
+//Packs variable-width codes LSB first and writes them out as data sub-blocks.
+struct CodeWriter
+{
+	block: Vec<u8>,
+	acc: u32,
+	bits: u8,
+}
+
+impl CodeWriter
+{
+	fn new() -> CodeWriter
+	{
+		CodeWriter{block: Vec::with_capacity(255), acc: 0, bits: 0}
+	}
+	fn write<W:Write>(&mut self, out: &mut W, code: u16, size: u8)
+	{
+		self.acc |= (code as u32) << self.bits;
+		self.bits += size;
+		while self.bits >= 8 {
+			let b = self.acc as u8;
+			self.push(out, b);
+			self.acc >>= 8;
+			self.bits -= 8;
+		}
+	}
+	fn push<W:Write>(&mut self, out: &mut W, b: u8)
+	{
+		self.block.push(b);
+		if self.block.len() == 255 {
+			out.write_all(&[255]).unwrap();
+			out.write_all(&self.block).unwrap();
+			self.block.clear();
+		}
+	}
+	//Flush remaining bits and write the block terminator.
+	fn finish<W:Write>(mut self, out: &mut W)
+	{
+		if self.bits > 0 { let b = self.acc as u8; self.push(out, b); }
+		if self.block.len() > 0 {
+			out.write_all(&[self.block.len() as u8]).unwrap();
+			out.write_all(&self.block).unwrap();
+		}
+		out.write_all(&[0]).unwrap();
+	}
+}
+
+//Write LZW-compressed image data, including the minimum code size.
+fn write_lzw<W:Write>(out: &mut W, min_size: u8, data: &[u8])
+{
+	out.write_all(&[min_size]).unwrap();
+	let clear = 1u16 << min_size;
+	let eoi = clear + 1;
+	let mut codes = CodeWriter::new();
+	let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+	let mut next = clear + 2;
+	let mut size = min_size + 1;
+	codes.write(out, clear, size);
+	let mut prefix: Option<u16> = None;
+	for &k in data.iter() {
+		if prefix.is_none() { prefix = Some(k as u16); continue; }
+		let p = prefix.unwrap();
+		if let Some(&c) = dict.get(&(p, k)) { prefix = Some(c); continue; }
+		codes.write(out, p, size);
+		dict.insert((p, k), next);
+		next += 1;
+		//The decoder adds codes one step behind, so switch size only after the code it would add.
+		if next > 1 << size && size < 12 { size += 1; }
+		if next > LZW_MAX_CODE {
+			codes.write(out, clear, size);
+			dict.clear();
+			next = clear + 2;
+			size = min_size + 1;
+		}
+		prefix = Some(k as u16);
+	}
+	if let Some(p) = prefix {
+		codes.write(out, p, size);
+		if next == 1 << size && size < 12 { size += 1; }
+	}
+	codes.write(out, eoi, size);
+	codes.finish(out);
+}
+
+//Writes animated GIF frame by frame, using one global palette. Every frame is drawn over the previous one.
+pub struct GifWriter
+{
+	index: HashMap<u32, u8>,
+	min_size: u8,
+	delay: u16,
+}
+
+impl GifWriter
+{
+	//Write the headers. Colors are RGB, index 0 is reserved for unwritten cells. Delay is in centiseconds. Returns
+	//None if there are more than 255 colors.
+	pub fn new<W:Write>(out: &mut W, width: usize, height: usize, colors: &[u32], delay: u16) -> Option<GifWriter>
+	{
+		if colors.len() > 255 { return None; }
+		let mut bits = 1;
+		while (1 << bits) < colors.len() + 1 { bits += 1; }
+		out.write_all(b"GIF89a").unwrap();
+		out.write_all(&le16(width)).unwrap();
+		out.write_all(&le16(height)).unwrap();
+		//Global color table, 8 bits per primary.
+		out.write_all(&[0xF0 | (bits - 1), 0, 0]).unwrap();
+		let mut table = vec![0;3 << bits];
+		let mut index = HashMap::new();
+		for (i, &c) in colors.iter().enumerate() {
+			(&mut table[3*i+3..][..3]).copy_from_slice(&[(c >> 16) as u8, (c >> 8) as u8, c as u8]);
+			index.insert(c, i as u8 + 1);
+		}
+		out.write_all(&table).unwrap();
+		//Loop forever.
+		out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00").unwrap();
+		Some(GifWriter{index: index, min_size: if bits < 2 { 2 } else { bits }, delay: delay})
+	}
+	//Write rectangle of img as the next frame. The rectangle must be within the image.
+	pub fn write_frame<W:Write,I:ImageSource>(&mut self, out: &mut W, img: &I, x: usize, y: usize, w: usize,
+		h: usize)
+	{
+		//Leave the frame in place, unwritten cells are transparent.
+		out.write_all(&[0x21, 0xF9, 4, 0x05]).unwrap();
+		out.write_all(&le16(self.delay as usize)).unwrap();
+		out.write_all(&[0, 0]).unwrap();
+		out.write_all(&[0x2C]).unwrap();
+		for &v in [x, y, w, h].iter() { out.write_all(&le16(v)).unwrap(); }
+		out.write_all(&[0]).unwrap();
+		let mut buf = vec![0;4*w];
+		let mut data = Vec::with_capacity(w * h);
+		for j in 0..h {
+			img.read_row_at(y + j, x, &mut buf);
+			for px in buf.chunks(4) {
+				let c = (px[0] as u32) << 16 | (px[1] as u32) << 8 | (px[2] as u32);
+				data.push(if px[3] == 0 { 0 } else { self.index.get(&c).cloned().unwrap_or(0) });
+			}
+		}
+		write_lzw(out, self.min_size, &data);
+	}
+	pub fn finish<W:Write>(self, out: &mut W)
+	{
+		out.write_all(&[0x3B]).unwrap();
+	}
+}
@@ -0,0 +1,112 @@
+use ::db_connect;
+use ::cors::SendFileAsWithCors;
+use ::error::Error;
+use ::imagestate::ImageSource;
+use ::scene::Scene;
+use ::scene_endpoint::{GetBounds, PngView, scan_scaled_image_as_png};
+use ::sceneconfig::scene_size;
+use postgres::Connection;
+use rocket::response::Responder;
+use std::cmp::{max, min};
+use std::fmt::Write as FmtWrite;
+
+//Color ramp from least to most edited.
+const RAMP: [(f64, f64, f64); 5] = [(0.0, 0.0, 255.0), (0.0, 255.0, 255.0), (0.0, 255.0, 0.0), (255.0, 255.0, 0.0),
+	(255.0, 0.0, 0.0)];
+
+//Number of edits to each cell of scene.
+struct Heatmap
+{
+	counts: Vec<i64>,
+	width: usize,
+	height: usize,
+	max: i64,
+}
+
+impl Heatmap
+{
+	fn new(conn: &Connection, scene: Scene, range: &GetBounds) -> Result<Heatmap, Error>
+	{
+		let (w, h) = scene_size(conn, scene)?;
+		let (w, h) = (w as usize, h as usize);
+		let tstart = range.start.unwrap_or(i64::min_value());
+		let tend = range.end.unwrap_or(i64::max_value());
+		let mut counts = vec![0;w * h];
+		let mut most = 0;
+		for row in conn.query("SELECT x, y, COUNT(*) FROM scene_view WHERE sceneid=$1 AND timestamp>=$2 AND \
+			timestamp<=$3 GROUP BY x, y", &[&scene, &tstart, &tend]).unwrap().iter() {
+			let x: i32 = row.get(0);
+			let y: i32 = row.get(1);
+			let count: i64 = row.get(2);
+			if x < 0 || y < 0 || x as usize >= w || y as usize >= h { continue; }
+			counts[y as usize * w + x as usize] = count;
+			most = max(most, count);
+		}
+		Ok(Heatmap{counts: counts, width: w, height: h, max: most})
+	}
+}
+
+impl ImageSource for Heatmap
+{
+	fn get_size(&self) -> (usize, usize)
+	{
+		(self.width, self.height)
+	}
+	fn read_row_at(&self, y: usize, x: usize, buf: &mut [u8])
+	{
+		if x >= self.width { return; }
+		let offset = y * self.width + x;
+		//Logarithmic scale, so a few hot spots do not wash out everything else.
+		let scale = (self.max as f64).ln_1p();
+		for i in 0..min(self.width - x, buf.len() / 4) {
+			let count = self.counts[offset + i];
+			if count == 0 {
+				(&mut buf[4*i..][..4]).copy_from_slice(&[0, 0, 0, 0]);
+				continue;
+			}
+			let t = (if scale > 0.0 { (count as f64).ln_1p() / scale } else { 1.0 }) * (RAMP.len() - 1) as f64;
+			let j = min(t as usize, RAMP.len() - 2);
+			let f = t - j as f64;
+			let (a, b) = (RAMP[j], RAMP[j+1]);
+			(&mut buf[4*i..][..4]).copy_from_slice(&[(a.0 + (b.0 - a.0) * f) as u8, (a.1 + (b.1 - a.1) * f) as u8,
+				(a.2 + (b.2 - a.2) * f) as u8, 255]);
+		}
+	}
+}
+
+pub fn scene_get_heatmap(scene: Scene, range: GetBounds, view: PngView) -> Result<impl Responder<'static>, Error>
+{
+	let conn = db_connect();
+	let heatmap = Heatmap::new(&conn, scene, &range)?;
+	Ok(SendFileAsWithCors{
+		content_type: "application/png",
+		content: scan_scaled_image_as_png(&heatmap, &view)?,
+		methods: "HEAD, GET",
+		headers: ""
+	})
+}
+
+pub fn scene_get_heatmap_json(scene: Scene, range: GetBounds) -> Result<impl Responder<'static>, Error>
+{
+	let conn = db_connect();
+	let heatmap = Heatmap::new(&conn, scene, &range)?;
+	let mut out = String::new();
+	out.push_str(r#"{"counts":["#);
+	for y in 0..heatmap.height {
+		if y > 0 { out.push(','); }
+		out.push('[');
+		for (x, count) in heatmap.counts[y * heatmap.width..][..heatmap.width].iter().enumerate() {
+			if x > 0 { out.push(','); }
+			write!(out, "{}", count).unwrap();
+		}
+		out.push(']');
+	}
+	write!(out, r#"],"max":{},"width":{},"height":{}}}"#, heatmap.max, heatmap.width, heatmap.height).unwrap();
+	out.push('\n');
+	Ok(SendFileAsWithCors{
+		content_type: "application/json",
+		content: out.into_bytes(),
+		methods: "HEAD, GET",
+		headers: ""
+	})
+}
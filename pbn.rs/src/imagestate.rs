@@ -0,0 +1,98 @@
+use ::mmapstate::MmapImageState;
+use std::cmp::min;
+
+//Image that can be scanned row by row.
+pub trait ImageSource
+{
+	fn get_size(&self) -> (usize, usize);
+	//Read row as RGBA, starting from column x.
+	fn read_row_at(&self, y: usize, x: usize, buf: &mut [u8]);
+	fn read_row(&self, y: usize, buf: &mut [u8])
+	{
+		self.read_row_at(y, 0, buf)
+	}
+}
+
+//Image that events can be written into.
+pub trait ImageState: ImageSource
+{
+	fn write_pixel(&mut self, x: i32, y: i32, ts: i64, color: i32);
+}
+
+impl ImageSource for MmapImageState
+{
+	fn get_size(&self) -> (usize, usize)
+	{
+		MmapImageState::get_size(self)
+	}
+	fn read_row_at(&self, y: usize, x: usize, buf: &mut [u8])
+	{
+		MmapImageState::read_row_at(self, y, x, buf)
+	}
+}
+
+impl ImageState for MmapImageState
+{
+	fn write_pixel(&mut self, x: i32, y: i32, ts: i64, color: i32)
+	{
+		MmapImageState::write_pixel(self, x, y, ts, color)
+	}
+}
+
+//Scratch image not backed by anything. Starts out with all pixels unwritten.
+pub struct MemoryImageState
+{
+	pdatabase: Vec<u32>,
+	tdatabase: Vec<i64>,
+	width: usize,
+	height: usize,
+}
+
+impl MemoryImageState
+{
+	pub fn new(width: usize, height: usize) -> MemoryImageState
+	{
+		MemoryImageState{
+			pdatabase: vec![0;width * height],
+			tdatabase: vec![0;width * height],
+			width: width,
+			height: height,
+		}
+	}
+}
+
+impl ImageSource for MemoryImageState
+{
+	fn get_size(&self) -> (usize, usize)
+	{
+		(self.width, self.height)
+	}
+	fn read_row_at(&self, y: usize, x: usize, buf: &mut [u8])
+	{
+		if x >= self.width { return; }
+		let offset = y * self.width + x;
+		for i in 0..min(self.width - x, buf.len() / 4) {
+			let x = self.pdatabase[offset + i];
+			buf[4*i+0] = (x >> 16) as u8;
+			buf[4*i+1] = (x >> 8) as u8;
+			buf[4*i+2] = x as u8;
+			buf[4*i+3] = (x >> 24) as u8;
+		}
+	}
+}
+
+impl ImageState for MemoryImageState
+{
+	fn write_pixel(&mut self, x: i32, y: i32, ts: i64, color: i32)
+	{
+		if x < 0 || y < 0 { return; }
+		let x = x as usize;
+		let y = y as usize;
+		if x >= self.width || y >= self.height { return; }
+		let offset = y * self.width + x;
+		if self.tdatabase[offset] <= ts {
+			self.pdatabase[offset] = 0xFF000000 | (color & 0xFFFFFF) as u32;
+			self.tdatabase[offset] = ts;
+		}
+	}
+}
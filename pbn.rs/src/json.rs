@@ -478,8 +478,10 @@ pub enum BasicJsonError
 	//Expected start of array.
 	ExpectedStartOfArray(String),
 	//Expected string.
+	#[allow(dead_code)]
 	ExpectedString(String),
 	//Expected end of object.
+	#[allow(dead_code)]
 	ExpectedEndOfObject(String),
 	//Expected end of JSON.
 	ExpectedEnd(String),
@@ -592,8 +594,8 @@ impl<R:IoRead> JsonStream<R>
 			//There can be at most 3 pending bytes, since 4 should have combined into a character.
 			//Process the trailing incomplete UTF-8 part.
 			if n - valid_up_to > 3 { return Err(BasicJsonError::BadUtf8); }
-			for i in valid_up_to..n {
-				self.utf8_overflow = 256 * self.utf8_overflow + (buf[i] as u32);
+			for &b in &buf[valid_up_to..n] {
+				self.utf8_overflow = 256 * self.utf8_overflow + (b as u32);
 			}
 		}
 		Ok(())
@@ -639,7 +641,7 @@ impl<R:IoRead> JsonStream<R>
 	{
 		use self::BasicJsonError::*;
 		//Empty array case.
-		if self.peek().map_err(error.clone())? == JsonToken::EndObject { return Ok(()); }
+		if self.peek().map_err(error)? == JsonToken::EndObject { return Ok(()); }
 		
 		loop {
 			let keyname = match self.next(error)? {
@@ -666,6 +668,7 @@ impl<R:IoRead> JsonStream<R>
 			x => Err(BasicJsonError::ExpectedStartOfObject(format!("{:?}", x)))
 		}
 	}
+	#[allow(dead_code)]
 	pub fn expect_object_end(&mut self) -> Result<(), BasicJsonError>
 	{
 		match self.peek()? {
@@ -680,6 +683,7 @@ impl<R:IoRead> JsonStream<R>
 			x => Err(BasicJsonError::ExpectedStartOfArray(format!("{:?}", x)))
 		}
 	}
+	#[allow(dead_code)]
 	pub fn expect_string(&mut self) -> Result<String, BasicJsonError>
 	{
 		match self.peek()? {
@@ -687,6 +691,7 @@ impl<R:IoRead> JsonStream<R>
 			x => Err(BasicJsonError::ExpectedString(format!("{:?}", x)))
 		}
 	}
+	#[allow(dead_code)]
 	pub fn expect_doublecolon(&mut self) -> Result<(), BasicJsonError>
 	{
 		match self.peek()? {
@@ -705,8 +710,8 @@ impl<R:IoRead> JsonStream<R>
 
 pub fn escape_json_string<'a>(x: &'a str) -> Cow<'a, str>
 {
-	if x.find(|c|{let c = c as u32; c < 32 || c == 34 || c == 92  /*controls, doublequote or backslash.*/}).
-		is_none() { return Cow::Borrowed(x); }
+	let special = |c|{let c = c as u32; c < 32 || c == 34 || c == 92  /*controls, doublequote or backslash.*/};
+	if x.find(special).is_none() { return Cow::Borrowed(x); }
 	//Needs escaping.
 	let mut out = String::new();
 	for c in x.chars() {
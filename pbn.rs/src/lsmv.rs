@@ -1,4 +1,7 @@
 use super::{db_connect,Error, Scene, add_default_headers};
+use ::scene_endpoint::{EventFilter, EVENTS_WITH_ORIGIN};
+use ::sceneconfig::scene_size;
+use postgres::types::ToSql;
 use rocket::request::Request;
 use rocket::response::{Responder, Response};
 use rocket::http::{Header, Status};
@@ -137,20 +140,17 @@ impl<'r> Responder<'r> for SendFileAs
 	}
 }
 
-pub fn scene_get_lsmv(scene: Scene) -> Result<SendFileAs, Error>
+pub fn scene_get_lsmv(scene: Scene, filter: EventFilter) -> Result<SendFileAs, Error>
 {
 	let oldscene = from_utf8(&scene.scramble()).unwrap().to_owned();
 	let conn = db_connect();
-	let (w, h) = if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1", &[&scene]).
-		unwrap().iter().next() {
-		let w: i32 = row.get(0);
-		let h: i32 = row.get(1);
-		(w, h)
-	} else {
-		return Err(Error::SceneNotFound);
-	};
-	let moviedata = conn.query("SELECT timestamp,color,x,y FROM scene_data WHERE sceneid=$1 ORDER BY \
-		timestamp, recordid", &[&scene]).unwrap().iter().filter_map(|ev|{
+	let (w, h) = scene_size(&conn, scene)?;
+	let mut params: Vec<&ToSql> = Vec::new();
+	params.push(&scene);
+	params.extend_from_slice(&filter.params());
+	let moviedata = conn.query(&format!("SELECT d.timestamp,d.color,d.x+s.xorigin,d.y+s.yorigin FROM {} WHERE \
+		d.sceneid=$1 AND {} ORDER BY d.timestamp, d.recordid", EVENTS_WITH_ORIGIN, EventFilter::condition(2)),
+		&params).unwrap().iter().filter_map(|ev|{
 		let ts: i64 = ev.get(0);
 		let color: i32 = ev.get(1);
 		let x: i32 = ev.get(2);
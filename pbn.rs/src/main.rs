@@ -1,6 +1,14 @@
-#![feature(plugin, decl_macro, custom_derive, test, conservative_impl_trait)]
+#![feature(plugin, decl_macro, custom_derive, test)]
 #![plugin(rocket_codegen)]
 #![deny(unsafe_code)]
+//Style used throughout, redundant_closure and suspicious_else_formatting also trip on rocket_codegen output.
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::redundant_field_names, clippy::unreadable_literal,
+	clippy::useless_format, clippy::needless_return, clippy::needless_pass_by_value, clippy::needless_lifetimes,
+	clippy::extra_unused_lifetimes, clippy::len_zero, clippy::cast_lossless, clippy::const_static_lifetime,
+	clippy::identity_op, clippy::many_single_char_names, clippy::match_ref_pats, clippy::new_ret_no_self,
+	clippy::new_without_default_derive, clippy::or_fun_call, clippy::single_char_pattern,
+	clippy::trivially_copy_pass_by_ref, clippy::cyclomatic_complexity, clippy::type_complexity,
+	clippy::redundant_closure, clippy::suspicious_else_formatting))]
 
 extern crate rocket;
 extern crate postgres;
@@ -8,12 +16,18 @@ extern crate md5;
 extern crate rand;
 extern crate libc;
 extern crate time;
+extern crate fallible_iterator;
+extern crate ws;
 use postgres::{Connection, TlsMode};
 use rocket::request::Form;
 use rocket::response::{Response, Responder};
-use rocket::http::Header;
+use rocket::http::{Header, RawStr};
+use rocket::request::FromParam;
 use rocket::Data;
 use std::char::from_u32;
+use std::cmp::max;
+use std::env::args;
+use std::process::exit;
 use std::fs::File;
 use std::path::Path;
 use std::io::Read as IoRead;
@@ -21,7 +35,9 @@ use std::fmt::Write as FmtWrite;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::thread::spawn;
 use std::ffi::CStr;
+use std::str::FromStr;
 use libc::{getuid, getpwuid};
 
 #[macro_use]
@@ -37,21 +53,39 @@ mod authentication;
 use authentication::AuthenticationInfo;
 mod scene;
 use scene::Scene;
+mod sceneconfig;
 mod mmapstate;
+mod imagestate;
+mod replay;
+use replay::rebuild_scene;
 mod png;
+mod gif;
+mod heatmap;
+use heatmap::{scene_get_heatmap as _scene_get_heatmap, scene_get_heatmap_json as _scene_get_heatmap_json};
+mod stats;
+use stats::scene_get_user_stats as _scene_get_user_stats;
+mod export;
+use export::scene_get_export as _scene_get_export;
 mod staticfile;
 use staticfile::serve_file;
 mod cors;
 mod scenes_endpoint;
 use scenes_endpoint::{scenes_get as _scenes_get, scenes_options as _scenes_options, scenes_post as _scenes_post,
-	SceneInfo};
+	scene_fork as _scene_fork, scene_fork_options as _scene_fork_options, SceneInfo, ForkInfo};
 mod scene_endpoint;
 use scene_endpoint::{scene_get as _scene_get, scene_options as _scene_options,
 	scene_edit_delete as _scene_edit_delete, scene_edit_options as _scene_edit_options,
 	scene_edit_post as _scene_edit_post, scene_edit_put as _scene_edit_put, scene_get_png as _scene_get_png,
-	scene_get_lsmv as _scene_get_lsmv, GetBounds, ScenePostForm, scene_config_options as _scene_config_options,
-	scene_config_get as _scene_config_get, scene_config_put as _scene_config_put,
+	scene_get_lsmv as _scene_get_lsmv, scene_get_apng as _scene_get_apng, scene_get_gif as _scene_get_gif,
+	scene_get_pixel as _scene_get_pixel, scene_get_cooldown as _scene_get_cooldown, GetBounds, PngView, TimelapseView,
+	ScenePostForm, EventFilter, scene_get_protected as _scene_get_protected,
+	scene_config_options as _scene_config_options, scene_config_get as _scene_config_get,
+	scene_config_put as _scene_config_put,
 	scene_describe as _scene_describe, Xss};
+mod scene_events;
+use scene_events::{scene_get_events as _scene_get_events, set_max_waiters, LastEventId};
+mod websocket;
+use websocket::websocket_server;
 mod nistpqctest;
 use nistpqctest::nistpqctest as _nistpqctest;
 
@@ -87,6 +121,21 @@ fn scenes_post(auth: AuthenticationInfo, upload: Form<SceneInfo>) -> impl Respon
 	_scenes_post(auth, upload)
 }
 
+#[options("/scenes/<scene>/fork")]
+fn scene_fork_options(scene: Option<Scene>) -> Result<impl Responder<'static>, Error>
+{
+	scene.ok_or(Error::SceneNotFound)?;
+	_scene_fork_options()
+}
+
+#[post("/scenes/<scene>/fork", data = "<upload>")]
+fn scene_fork(scene: Option<Scene>, auth: AuthenticationInfo, upload: Form<ForkInfo>) ->
+	Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_fork(scene, auth, upload)
+}
+
 #[options("/scenes/<scene>")]
 fn scene_options(scene: Option<Scene>) -> Result<impl Responder<'static>, Error>
 {
@@ -95,10 +144,17 @@ fn scene_options(scene: Option<Scene>) -> Result<impl Responder<'static>, Error>
 }
 
 #[get("/scenes/<scene>")]
-fn scene_get(scene: Option<Scene>, range: GetBounds) -> Result<impl Responder<'static>, Error>
+fn scene_get(scene: Option<Scene>, range: GetBounds, filter: EventFilter) -> Result<impl Responder<'static>, Error>
 {
 	let scene = scene.ok_or(Error::SceneNotFound)?;
-	_scene_get(scene, range)
+	_scene_get(scene, range, filter)
+}
+
+#[get("/scenes/<scene>/events")]
+fn scene_get_events(scene: Option<Scene>, last: LastEventId) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_events(scene, last)
 }
 
 #[options("/scenes/<scene>/edit")]
@@ -136,17 +192,82 @@ fn scene_edit_delete(scene: Option<Scene>, auth: AuthenticationInfo, upload: Dat
 }
 
 #[get("/scenes/<scene>/png")]
-fn scene_get_png(scene: Option<Scene>) -> Result<impl Responder<'static>, Error>
+fn scene_get_png(scene: Option<Scene>, range: GetBounds, view: PngView) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_png(scene, range, view)
+}
+
+#[get("/scenes/<scene>/apng")]
+fn scene_get_apng(scene: Option<Scene>, range: GetBounds, view: TimelapseView) ->
+	Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_apng(scene, range, view)
+}
+
+#[get("/scenes/<scene>/gif")]
+fn scene_get_gif(scene: Option<Scene>, range: GetBounds, view: TimelapseView) ->
+	Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_gif(scene, range, view)
+}
+
+#[get("/scenes/<scene>/heatmap")]
+fn scene_get_heatmap(scene: Option<Scene>, range: GetBounds, view: PngView) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_heatmap(scene, range, view)
+}
+
+#[get("/scenes/<scene>/heatmap.json")]
+fn scene_get_heatmap_json(scene: Option<Scene>, range: GetBounds) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_heatmap_json(scene, range)
+}
+
+#[get("/scenes/<scene>/pixel/<x>/<y>")]
+fn scene_get_pixel(scene: Option<Scene>, x: i32, y: i32) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_pixel(scene, x, y)
+}
+
+#[get("/scenes/<scene>/stats/users")]
+fn scene_get_user_stats(scene: Option<Scene>) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_user_stats(scene)
+}
+
+#[get("/scenes/<scene>/cooldown/<user>")]
+fn scene_get_cooldown(scene: Option<Scene>, user: String) -> Result<impl Responder<'static>, Error>
 {
 	let scene = scene.ok_or(Error::SceneNotFound)?;
-	_scene_get_png(scene)
+	_scene_get_cooldown(scene, user)
+}
+
+#[get("/scenes/<scene>/protected")]
+fn scene_get_protected(scene: Option<Scene>) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_protected(scene)
+}
+
+#[get("/scenes/<scene>/export")]
+fn scene_get_export(scene: Option<Scene>, range: GetBounds) -> Result<impl Responder<'static>, Error>
+{
+	let scene = scene.ok_or(Error::SceneNotFound)?;
+	_scene_get_export(scene, range)
 }
 
 #[get("/scenes/<scene>/lsmv")]
-fn scene_get_lsmv(scene: Option<Scene>) -> Result<impl Responder<'static>, Error>
+fn scene_get_lsmv(scene: Option<Scene>, filter: EventFilter) -> Result<impl Responder<'static>, Error>
 {
 	let scene = scene.ok_or(Error::SceneNotFound)?;
-	_scene_get_lsmv(scene)
+	_scene_get_lsmv(scene, filter)
 }
 
 #[options("/scenes/<scene>/config")]
@@ -174,10 +295,10 @@ fn scene_config_put(scene: Option<Scene>, auth: AuthenticationInfo, upload: Data
 }
 
 #[get("/scenes/<scene>/describe")]
-fn scene_describe(scene: Option<Scene>, xss: Xss) -> Result<impl Responder<'static>, Error>
+fn scene_describe(scene: Option<Scene>, xss: Xss, filter: EventFilter) -> Result<impl Responder<'static>, Error>
 {
 	let scene = scene.ok_or(Error::SceneNotFound)?;
-	_scene_describe(scene, xss)
+	_scene_describe(scene, xss, filter)
 }
 
 //Pathbuf as parameter does not accept path transversal.
@@ -229,8 +350,29 @@ fn sink_put<T:Sized>(upload: Data, error: T) -> T
 	sink_put_remaining(upload.open(), error)
 }
 
+//Command line mode: Recompute the current state of scenes given as arguments.
+fn rebuild_main(scenes: &[String])
+{
+	let conn = db_connect();
+	let mut failed = false;
+	for i in scenes.iter() {
+		let res = Scene::from_param(RawStr::from_str(i)).and_then(|scene|rebuild_scene(&conn, scene));
+		match res {
+			Ok(_) => eprintln!("Rebuilt scene {}", i),
+			Err(x) => { eprintln!("Failed to rebuild scene {}: {:?}", i, x); failed = true; }
+		}
+	}
+	exit(if failed { 1 } else { 0 });
+}
+
 fn main() {
-	rocket::ignite().mount("/", routes![
+	let cmdline: Vec<String> = args().collect();
+	if cmdline.len() > 1 && cmdline[1] == "rebuild" { rebuild_main(&cmdline[2..]); }
+	spawn(websocket_server);
+	let rocket = rocket::ignite();
+	//By default, leave half of the workers for requests that do not wait.
+	set_max_waiters(max_waiters().unwrap_or(rocket.config().workers as usize / 2));
+	rocket.mount("/", routes![
 		//Static files,
 		serve_static_files,
 		//Applications.
@@ -240,7 +382,17 @@ fn main() {
 		scene_get,
 		scene_get_lsmv,
 		scene_get_png,
+		scene_get_apng,
+		scene_get_gif,
+		scene_get_heatmap,
+		scene_get_heatmap_json,
+		scene_get_pixel,
+		scene_get_user_stats,
+		scene_get_cooldown,
+		scene_get_protected,
+		scene_get_export,
 		scene_describe,
+		scene_get_events,
 		//Scene edit.
 		scene_edit_options,
 		scene_edit_put,
@@ -254,6 +406,8 @@ fn main() {
 		scenes_options,
 		scenes_get,
 		scenes_post,
+		scene_fork_options,
+		scene_fork,
 		//test
 		nistpqctest,
 	]).launch();
@@ -266,6 +420,10 @@ struct Config
 	db_name: String,
 	scene_key: Vec<u8>,
 	rootpath: String,
+	websocket_addr: String,
+	websocket_connections: usize,
+	websocket_workers: usize,
+	max_waiters: Option<usize>,
 }
 
 impl Config
@@ -276,9 +434,8 @@ impl Config
 		let pwd = unsafe{getpwuid(getuid())};
 		if pwd.is_null() { panic!("No home directory for current user in user database"); }
 		if unsafe{(*pwd).pw_uid} == 0 { panic!("Running as root??? Are you insane???"); }
-		let root = match unsafe{CStr::from_ptr((*pwd).pw_dir)}.to_str() { Ok(x) => x.to_owned(), Err(_) => {
-			panic!("User home directory in user database is not valid UTF-8");
-		}};
+		let root = unsafe{CStr::from_ptr((*pwd).pw_dir)}.to_str().
+			expect("User home directory in user database is not valid UTF-8").to_owned();
 		let cfilename = format!("{}/pbn.conf", root);
 		let mut content = String::new();
 		match File::open(&cfilename).and_then(|mut f|f.read_to_string(&mut content)) {
@@ -290,12 +447,20 @@ impl Config
 		let dpath = i.next().unwrap().to_owned();
 		let dname = i.next().unwrap().to_owned();
 		let key = i.next().unwrap().as_bytes().to_owned();
+		let wsaddr = i.next().map(|x|x.to_owned()).unwrap_or_else(||"127.0.0.1:8001".to_owned());
+		let wsconns = i.next().and_then(|x|usize::from_str(x.trim()).ok()).unwrap_or(100);
+		let wsworkers = i.next().and_then(|x|usize::from_str(x.trim()).ok()).map(|x|max(x, 1)).unwrap_or(4);
+		let waiters = i.next().and_then(|x|usize::from_str(x.trim()).ok());
 		Config{
 			db_user: duser,
 			db_path: dpath,
 			db_name: dname,
 			scene_key: key,
 			rootpath: root,
+			websocket_addr: wsaddr,
+			websocket_connections: wsconns,
+			websocket_workers: wsworkers,
+			max_waiters: waiters,
 		}
 	}
 	fn get<F>(mut cb: F) where F: FnMut(&Config)
@@ -346,6 +511,42 @@ fn root_path() -> String
 	path
 }
 
+fn websocket_address() -> String
+{
+	let mut addr = String::new();
+	Config::get(|c|{
+		addr = c.websocket_addr.clone();
+	});
+	addr
+}
+
+fn websocket_connections() -> usize
+{
+	let mut conns = 0;
+	Config::get(|c|{
+		conns = c.websocket_connections;
+	});
+	conns
+}
+
+fn websocket_workers() -> usize
+{
+	let mut workers = 0;
+	Config::get(|c|{
+		workers = c.websocket_workers;
+	});
+	workers
+}
+
+fn max_waiters() -> Option<usize>
+{
+	let mut waiters = None;
+	Config::get(|c|{
+		waiters = c.max_waiters;
+	});
+	waiters
+}
+
 fn add_default_headers(response: &mut Response)
 {
 	response.set_header(Header::new("X-XSS-Protection", "0"));
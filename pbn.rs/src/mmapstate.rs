@@ -5,7 +5,7 @@ use libc::{c_int, stat, fstat, mmap, open, munmap, close, ssize_t, /*read, */wri
 use std::path::Path;
 use std::ptr::{read_volatile, write_volatile, null};
 //use std::slice::from_raw_parts_mut;
-use std::mem::{transmute, zeroed};
+use std::mem::zeroed;
 use std::io::Error as IoError;
 use std::os::unix::ffi::OsStrExt;
 use std::ffi::CString;
@@ -43,7 +43,7 @@ impl FileDescriptor
 */
 	fn write(&self, buf: &[u8]) -> Result<ssize_t, IoError>
 	{
-		libc_error(unsafe{write(self.0, transmute(buf.as_ptr()), buf.len())})
+		libc_error(unsafe{write(self.0, buf.as_ptr() as *const c_void, buf.len())})
 	}
 	fn stat(&self, st: &mut stat) -> Result<(), IoError>
 	{
@@ -59,7 +59,7 @@ impl FileDescriptor
 		unsafe {
 			let ptr = mmap(null::<c_void>() as _, size as _, prot, flags, self.0, offset);
 			if ptr == MAP_FAILED { return Err(IoError::last_os_error()); }
-			Ok(MappedArea(transmute(ptr), size))
+			Ok(MappedArea(ptr as *mut T, size))
 		}
 	}
 }
@@ -71,7 +71,7 @@ impl<T:Copy> Drop for MappedArea<T>
 {
 	fn drop(&mut self)
 	{
-		unsafe{munmap(transmute(self.0), self.1)};
+		unsafe{munmap(self.0 as *mut c_void, self.1)};
 	}
 }
 
@@ -80,12 +80,12 @@ impl<T:Copy> MappedArea<T>
 	fn read(&self, offset: usize) -> T
 	{
 		if (offset+1)*size_of::<T>() > self.1 { panic!("Mapped area read out of range"); }
-		unsafe{read_volatile(self.0.offset(offset as isize))}
+		unsafe{read_volatile(self.0.add(offset))}
 	}
 	fn write(&self, offset: usize, value: T)
 	{
 		if (offset+1)*size_of::<T>() > self.1 { panic!("Mapped area write out of range"); }
-		unsafe{write_volatile(self.0.offset(offset as isize), value)}
+		unsafe{write_volatile(self.0.add(offset), value)}
 	}
 }
 
@@ -144,14 +144,54 @@ impl MmapImageState
 			self.tdatabase.write(offset, ts);
 		}
 	}
+	//Make pixel unwritten, as if it was never written.
+	pub fn reset_pixel(&self, x: i32, y: i32)
+	{
+		if x < 0 || y < 0 { return; }
+		let x = x as usize;
+		let y = y as usize;
+		if x >= self.width || y >= self.height { return; }
+		let offset = y * self.width + x;
+		self.pdatabase.write(offset, 0);
+		self.tdatabase.write(offset, 0);
+	}
+	//Returns color and timestamp of pixel, or None if it is unwritten.
+	pub fn read_pixel(&self, x: i32, y: i32) -> Option<(u32, i64)>
+	{
+		if x < 0 || y < 0 { return None; }
+		let x = x as usize;
+		let y = y as usize;
+		if x >= self.width || y >= self.height { return None; }
+		let offset = y * self.width + x;
+		let color = self.pdatabase.read(offset);
+		if color >> 24 == 0 { return None; }
+		Some((color & 0xFFFFFF, self.tdatabase.read(offset)))
+	}
+	//Copy every cell of other into this, moved by dx and dy. Cells that end up outside are dropped.
+	pub fn copy_from(&self, other: &MmapImageState, dx: i32, dy: i32)
+	{
+		for y in 0..other.height {
+			let ty = y as i64 + dy as i64;
+			if ty < 0 || ty >= self.height as i64 { continue; }
+			for x in 0..other.width {
+				let tx = x as i64 + dx as i64;
+				if tx < 0 || tx >= self.width as i64 { continue; }
+				let from = y * other.width + x;
+				let to = ty as usize * self.width + tx as usize;
+				self.pdatabase.write(to, other.pdatabase.read(from));
+				self.tdatabase.write(to, other.tdatabase.read(from));
+			}
+		}
+	}
 	pub fn get_size(&self) -> (usize, usize)
 	{
 		(self.width, self.height)
 	}
-	pub fn read_row(&self, y: usize, buf: &mut [u8])
+	pub fn read_row_at(&self, y: usize, x: usize, buf: &mut [u8])
 	{
-		let offset = y * self.width;
-		for i in 0..min(self.width, buf.len() / 4) {
+		if x >= self.width { return; }
+		let offset = y * self.width + x;
+		for i in 0..min(self.width - x, buf.len() / 4) {
 			let x = self.pdatabase.read(offset + i);
 			buf[4*i+0] = (x >> 16) as u8;
 			buf[4*i+1] = (x >> 8) as u8;
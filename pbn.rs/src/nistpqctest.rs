@@ -2,9 +2,11 @@ use ::{db_connect};
 use ::xml::{XmlSerializer, XmlOutputStream};
 use ::xml::xhtml::Html;
 use ::xml::CONTENT_TYPE_XHTML;
+use postgres::Connection;
 use std::borrow::ToOwned;
 
-fn emit_table(xml: &mut XmlSerializer, classid: i32)
+#[allow(dead_code)]
+fn emit_table(conn: &Connection, xml: &mut XmlSerializer, classid: i32)
 {
 	xml.tag_fn(tag!(table), |xml|{
 		xml.tag_fn(tag!(tr), |xml|{
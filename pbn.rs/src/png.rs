@@ -1,26 +1,13 @@
+use libc::{c_char, c_int, c_uint, c_ulong, c_void};
+use std::cell::RefCell;
 use std::io::Write;
-use std::ptr::null;
-use ::mmapstate::MmapImageState;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::mem::{size_of, swap};
+use std::ptr::{null, null_mut};
+use ::imagestate::ImageSource;
 use std::io::Error as IoError;
 
-#[derive(Copy,Clone,Debug)]
-struct Adler32(u32);
-
-impl Adler32
-{
-	#[allow(unsafe_code)]
-	fn new() -> Adler32
-	{
-		Adler32(unsafe{adler32(0, null(), 0)})
-	}
-	#[allow(unsafe_code)]
-	fn add(&mut self, data: &[u8])
-	{
-		self.0 = unsafe{adler32(self.0, data.as_ptr(), data.len() as u32)};
-	}
-	fn as_inner(&self) -> u32 { self.0 }
-}
-
 #[derive(Copy,Clone,Debug)]
 struct Crc32(u32);
 
@@ -48,42 +35,207 @@ impl Crc32
 	}
 }
 
-//Write the first IDAT chunk.
-fn write_first_idat<W:Write>(out: &mut W)
+//Size of zlib z_stream structure must match the C one.
+#[repr(C)]
+struct ZStream
+{
+	next_in: *const u8,
+	avail_in: c_uint,
+	total_in: c_ulong,
+	next_out: *mut u8,
+	avail_out: c_uint,
+	total_out: c_ulong,
+	msg: *const c_char,
+	state: *mut c_void,
+	zalloc: *const c_void,
+	zfree: *const c_void,
+	opaque: *mut c_void,
+	data_type: c_int,
+	adler: c_ulong,
+	reserved: c_ulong,
+}
+
+const Z_NO_FLUSH: c_int = 0;
+const Z_FINISH: c_int = 4;
+const Z_STREAM_END: c_int = 1;
+
+//Zlib-format compressor.
+struct Deflater(Box<ZStream>);
+
+impl Deflater
+{
+	#[allow(unsafe_code)]
+	fn new(level: i32) -> Deflater
+	{
+		//The stream is boxed, because zlib does not allow it to move after initialization.
+		let mut strm = Box::new(ZStream{
+			next_in: null(),
+			avail_in: 0,
+			total_in: 0,
+			next_out: null_mut(),
+			avail_out: 0,
+			total_out: 0,
+			msg: null(),
+			state: null_mut(),
+			zalloc: null(),
+			zfree: null(),
+			opaque: null_mut(),
+			data_type: 0,
+			adler: 0,
+			reserved: 0,
+		});
+		let r = unsafe{deflateInit_(&mut *strm, level as c_int, b"1.2.11\0".as_ptr() as *const c_char,
+			size_of::<ZStream>() as c_int)};
+		if r != 0 { panic!("deflateInit failed: {}", r); }
+		Deflater(strm)
+	}
+	//Compress data, passing any output produced to sink. If finish is set, this is the last data.
+	#[allow(unsafe_code)]
+	fn compress<F>(&mut self, data: &[u8], finish: bool, mut sink: F) where F: FnMut(&[u8])
+	{
+		let mut buf = [0;16384];
+		self.0.next_in = data.as_ptr();
+		self.0.avail_in = data.len() as c_uint;
+		loop {
+			self.0.next_out = buf.as_mut_ptr();
+			self.0.avail_out = buf.len() as c_uint;
+			let r = unsafe{deflate(&mut *self.0, if finish { Z_FINISH } else { Z_NO_FLUSH })};
+			if r < 0 { panic!("deflate failed: {}", r); }
+			let produced = buf.len() - self.0.avail_out as usize;
+			if produced > 0 { sink(&buf[..produced]); }
+			//Without finish, all input has been consumed when there is output space left over.
+			if finish && r == Z_STREAM_END { break; }
+			if !finish && self.0.avail_out > 0 { break; }
+		}
+		self.0.next_in = null();
+		self.0.avail_in = 0;
+	}
+}
+
+impl Drop for Deflater
 {
+	#[allow(unsafe_code)]
+	fn drop(&mut self)
+	{
+		unsafe{deflateEnd(&mut *self.0)};
+	}
+}
+
+fn be32(x: u32) -> [u8;4]
+{
+	[(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]
+}
+
+fn write_chunk<W:Write>(out: &mut W, ctype: &[u8;4], data: &[u8])
+{
+	out.write_all(&be32(data.len() as u32)).unwrap();
 	let mut crc = Crc32::new();
-	out.write_all(&[0, 0, 0, 2]).unwrap();
-	crc.add_wt(&[73, 68, 65, 84, 8, 29], out).unwrap();
+	crc.add_wt(ctype, out).unwrap();
+	crc.add_wt(data, out).unwrap();
 	crc.flush(out).unwrap();
 }
 
-//sdata must be of exactly one scanline, and must be at most 65534 bytes.
-fn write_scanline<W:Write>(out: &mut W, sdata: &[u8], chksum: &mut Adler32, bottom: bool)
+//Maximum size of IDAT chunk written.
+const IDAT_SIZE: usize = 65536;
+
+//Compresses image data and writes it out as IDAT chunks, or as fdAT chunks if sequence number is given.
+struct IdatWriter
 {
-	let sdlen = sdata.len() + 6 + if bottom { 4 } else { 0 };
-	out.write_all(&[(sdlen >> 24) as u8, (sdlen >> 16) as u8, (sdlen >> 8) as u8, sdlen as u8]).unwrap();
-	let mut crc = Crc32::new();
-	let sdlena = ((sdata.len() + 1) >> 8) as u8;
-	let sdlenb = (sdata.len() + 1) as u8;
-	crc.add_wt(&[73, 68, 65, 84, if bottom { 1 } else { 0 }, sdlenb, sdlena, !sdlenb, !sdlena, 0], out).
-		unwrap();
-	chksum.add(&[0]);		//Zero is part of data.
-	chksum.add(sdata);
-	crc.add_wt(sdata, out).unwrap();
-	if bottom {
-		let chksum = chksum.as_inner();
-		crc.add_wt(&[(chksum >> 24) as u8, (chksum >> 16) as u8, (chksum >> 8) as u8, chksum as u8], out).
-			unwrap();
+	deflater: Deflater,
+	pending: Vec<u8>,
+	sequence: Option<u32>,
+}
+
+//Write one chunk of image data. Sequence number is advanced for fdAT chunks.
+fn write_data_chunk<W:Write>(out: &mut W, sequence: &mut Option<u32>, data: &[u8])
+{
+	match sequence {
+		&mut Some(ref mut seq) => {
+			let mut fdat = Vec::with_capacity(data.len() + 4);
+			fdat.extend_from_slice(&be32(*seq));
+			fdat.extend_from_slice(data);
+			write_chunk(out, b"fdAT", &fdat);
+			*seq += 1;
+		},
+		&mut None => write_chunk(out, b"IDAT", data)
 	}
-	crc.flush(out).unwrap();
 }
 
-fn write_ihdr<W:Write>(out: &mut W, w: usize, h: usize)
+impl IdatWriter
+{
+	fn new(level: i32, sequence: Option<u32>) -> IdatWriter
+	{
+		IdatWriter{deflater: Deflater::new(level), pending: Vec::with_capacity(IDAT_SIZE), sequence: sequence}
+	}
+	fn write<W:Write>(&mut self, out: &mut W, data: &[u8], finish: bool)
+	{
+		let pending = &mut self.pending;
+		let sequence = &mut self.sequence;
+		self.deflater.compress(data, finish, |x|{
+			pending.extend_from_slice(x);
+			while pending.len() >= IDAT_SIZE {
+				write_data_chunk(out, sequence, &pending[..IDAT_SIZE]);
+				pending.drain(..IDAT_SIZE);
+			}
+		});
+		if finish && pending.len() > 0 {
+			write_data_chunk(out, sequence, &pending);
+			pending.clear();
+		}
+	}
+}
+
+pub(crate) fn paeth(a: u8, b: u8, c: u8) -> u8
+{
+	let p = a as i16 + b as i16 - c as i16;
+	let pa = (p - a as i16).abs();
+	let pb = (p - b as i16).abs();
+	let pc = (p - c as i16).abs();
+	if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+//Filter scanline, picking the filter with smallest sum of absolute differences. Writes filter type and filtered
+//data to out. bpp is bytes per pixel, prev is the previous scanline (zeroes for the first).
+fn filter_scanline(out: &mut Vec<u8>, row: &[u8], prev: &[u8], bpp: usize, try_filters: bool)
+{
+	out.clear();
+	out.push(0);
+	out.extend_from_slice(row);
+	if !try_filters { return; }
+	let mut best_cost: u64 = row.iter().map(|&x|(x as i8 as i16).abs() as u64).sum();
+	let mut candidate = Vec::with_capacity(row.len() + 1);
+	for ftype in 1..5 {
+		candidate.clear();
+		candidate.push(ftype);
+		let mut cost = 0;
+		for i in 0..row.len() {
+			let a = if i >= bpp { row[i-bpp] } else { 0 };
+			let b = prev[i];
+			let c = if i >= bpp { prev[i-bpp] } else { 0 };
+			let v = row[i].wrapping_sub(match ftype {
+				1 => a,
+				2 => b,
+				3 => ((a as u16 + b as u16) / 2) as u8,
+				_ => paeth(a, b, c),
+			});
+			cost += (v as i8 as i16).abs() as u64;
+			candidate.push(v);
+		}
+		if cost < best_cost {
+			best_cost = cost;
+			out.clear();
+			out.extend_from_slice(&candidate);
+		}
+	}
+}
+
+//Color type 6 is RGBA, 3 is indexed.
+fn write_ihdr<W:Write>(out: &mut W, w: usize, h: usize, depth: u8, ctype: u8)
 {
 	let mut crc = Crc32::new();
 	out.write_all(&[137,80,78,71,13,10,26,10,0,0,0,13]).unwrap();
 	crc.add_wt(&[73,72,68,82, (w >> 24) as u8, (w >> 16) as u8, (w >> 8) as u8, w as u8, (h >> 24) as u8,
-		(h >> 16) as u8, (h >> 8) as u8, h as u8, 8, 6, 0, 0, 0], out).unwrap();
+		(h >> 16) as u8, (h >> 8) as u8, h as u8, depth, ctype, 0, 0, 0], out).unwrap();
 	crc.flush(out).unwrap()
 }
 
@@ -95,36 +247,236 @@ fn write_iend<W:Write>(out: &mut W)
 	crc.flush(out).unwrap();
 }
 
-//Size of the scanned image.
-pub fn scan_image_as_png_size(img: &MmapImageState) -> usize
+//Rectangular part of image, magnified by integer factor. Only reads the needed part of each source row.
+pub struct ScaledView<'a,I:ImageSource+'a>
 {
-	//PNG signature, IHDR, fixed IDAT, IDAT end overhead and IEND.
-	let mut size = 8 + 3 * 12 + 13 + 2 + 4;
-	let (w, h) = img.get_size();
-	//For each row, 18 bytes of overhead.
-	size += h * (18 + 4 * w);
-	size
+	img: &'a I,
+	x: usize,
+	y: usize,
+	w: usize,
+	h: usize,
+	scale: usize,
+	//Unscaled source row, kept between reads.
+	row: RefCell<Vec<u8>>,
+}
+
+impl<'a,I:ImageSource+'a> ScaledView<'a,I>
+{
+	//The rectangle is clipped to the image. Returns None if nothing remains.
+	pub fn new(img: &'a I, x: usize, y: usize, w: usize, h: usize, scale: usize) -> Option<ScaledView<'a,I>>
+	{
+		let (iw, ih) = img.get_size();
+		if x >= iw || y >= ih || w == 0 || h == 0 || scale == 0 { return None; }
+		let w = min(w, iw - x);
+		Some(ScaledView{
+			img: img,
+			x: x,
+			y: y,
+			w: w,
+			h: min(h, ih - y),
+			scale: scale,
+			row: RefCell::new(vec![0;4 * w]),
+		})
+	}
+}
+
+impl<'a,I:ImageSource+'a> ImageSource for ScaledView<'a,I>
+{
+	fn get_size(&self) -> (usize, usize)
+	{
+		(self.w * self.scale, self.h * self.scale)
+	}
+	fn read_row_at(&self, y: usize, x: usize, buf: &mut [u8])
+	{
+		let w = self.w * self.scale;
+		if x >= w { return; }
+		let mut src = self.row.borrow_mut();
+		self.img.read_row_at(self.y + y / self.scale, self.x, &mut src);
+		for i in 0..min(w - x, buf.len() / 4) {
+			let j = (x + i) / self.scale;
+			(&mut buf[4*i..][..4]).copy_from_slice(&src[4*j..][..4]);
+		}
+	}
+}
+
+fn rgba_key(px: &[u8]) -> u32
+{
+	//All fully transparent pixels are the same.
+	if px[3] == 0 { return 0; }
+	(px[0] as u32) << 24 | (px[1] as u32) << 16 | (px[2] as u32) << 8 | (px[3] as u32)
 }
 
-//Scan all IDAT chunks.
-pub fn scan_image_as_png<W:Write>(out: &mut W, img: &MmapImageState)
+//Colors of image that has at most 256 of them. Transparent color, if any, is first.
+struct Palette
+{
+	colors: Vec<u32>,
+	index: HashMap<u32, u8>,
+	depth: u8,
+}
+
+impl Palette
+{
+	fn scan<I:ImageSource>(img: &I) -> Option<Palette>
+	{
+		let (w, h) = img.get_size();
+		let mut buf = vec![0;4*w];
+		let mut colors = Vec::new();
+		let mut seen = HashSet::new();
+		for y in 0..h {
+			img.read_row(y, &mut buf);
+			for px in buf.chunks(4) {
+				let key = rgba_key(px);
+				if seen.insert(key) {
+					if seen.len() > 256 { return None; }
+					colors.push(key);
+				}
+			}
+		}
+		if let Some(pos) = colors.iter().position(|&x|x == 0) {
+			colors.remove(pos);
+			colors.insert(0, 0);
+		}
+		let index = colors.iter().enumerate().map(|(i, &c)|(c, i as u8)).collect();
+		let depth = match colors.len() { 0...2 => 1, 3...4 => 2, 5...16 => 4, _ => 8 };
+		Some(Palette{colors: colors, index: index, depth: depth})
+	}
+	fn write<W:Write>(&self, out: &mut W)
+	{
+		let mut plte = Vec::with_capacity(3 * self.colors.len());
+		for c in self.colors.iter() {
+			plte.extend_from_slice(&[(c >> 24) as u8, (c >> 16) as u8, (c >> 8) as u8]);
+		}
+		write_chunk(out, b"PLTE", &plte);
+		//Only the transparent color needs an alpha entry, the rest default to opaque.
+		if self.colors.get(0) == Some(&0) { write_chunk(out, b"tRNS", &[0]); }
+	}
+	fn lookup(&self, key: u32) -> u8
+	{
+		if let Some(&i) = self.index.get(&key) { return i; }
+		//The image changed after scanning the palette, use the closest color.
+		let dist = |c: u32|(0..4).map(|i|{
+			let d = ((c >> (8 * i)) & 255) as i32 - ((key >> (8 * i)) & 255) as i32;
+			d * d
+		}).sum::<i32>();
+		self.colors.iter().enumerate().min_by_key(|&(_, &c)|dist(c)).map(|(i, _)|i as u8).unwrap_or(0)
+	}
+	//Convert RGBA row to packed indices.
+	fn convert_row(&self, out: &mut Vec<u8>, row: &[u8])
+	{
+		out.clear();
+		let per_byte = 8 / self.depth as usize;
+		for px in row.chunks(4 * per_byte) {
+			let mut b: u16 = 0;
+			for i in 0..per_byte {
+				b <<= self.depth;
+				if let Some(p) = px.get(4*i..4*i+4) { b |= self.lookup(rgba_key(p)) as u16; }
+			}
+			out.push(b as u8);
+		}
+	}
+}
+
+//Filter and compress rows of image. Rows are converted to palette indices if palette is given.
+fn write_image_data<W:Write,I:ImageSource>(out: &mut W, img: &I, palette: Option<&Palette>, idat: &mut IdatWriter,
+	level: i32)
 {
 	let (w, h) = img.get_size();
 	let mut buf = vec![0;4*w];
-	write_ihdr(out, w, h);
-	write_first_idat(out);
-	let mut chksum = Adler32::new();
+	let mut row = Vec::with_capacity(4*w);
+	let mut filtered = Vec::with_capacity(4*w+1);
+	let (mut prev, bpp) = match palette {
+		Some(palette) => (vec![0;(w * palette.depth as usize + 7) / 8], 1),
+		None => (vec![0;4*w], 4)
+	};
 	for y in 0..h {
 		img.read_row(y, &mut buf);
-		write_scanline(out, &buf, &mut chksum, y + 1 == h);
+		match palette {
+			Some(palette) => palette.convert_row(&mut row, &buf),
+			None => { row.clear(); row.extend_from_slice(&buf); }
+		}
+		//Filtering is pointless without compression.
+		filter_scanline(&mut filtered, &row, &prev, bpp, level > 0);
+		idat.write(out, &filtered, y + 1 == h);
+		swap(&mut row, &mut prev);
+	}
+	if h == 0 { idat.write(out, &[], true); }
+}
+
+//Scan image and write it as PNG. Level is the compression level, 0 (none) to 9 (best). Images with at most 256
+//colors are written with palette.
+pub fn scan_image_as_png<W:Write,I:ImageSource>(out: &mut W, img: &I, level: i32)
+{
+	let (w, h) = img.get_size();
+	let palette = Palette::scan(img);
+	match palette {
+		Some(ref palette) => {
+			write_ihdr(out, w, h, palette.depth, 3);
+			palette.write(out);
+		},
+		None => write_ihdr(out, w, h, 8, 6)
 	}
+	let mut idat = IdatWriter::new(level, None);
+	write_image_data(out, img, palette.as_ref(), &mut idat, level);
 	write_iend(out);
 }
 
+//Writes animated PNG frame by frame. Every frame replaces a rectangle of the previous one.
+pub struct ApngWriter
+{
+	width: usize,
+	height: usize,
+	fps: u16,
+	level: i32,
+	//Sequence number of the next fcTL or fdAT chunk.
+	sequence: u32,
+}
+
+impl ApngWriter
+{
+	//Write the headers. Exactly frames frames must be written afterwards, the first covering the whole image.
+	pub fn new<W:Write>(out: &mut W, width: usize, height: usize, frames: u32, fps: u16, level: i32) -> ApngWriter
+	{
+		write_ihdr(out, width, height, 8, 6);
+		let mut actl = Vec::with_capacity(8);
+		actl.extend_from_slice(&be32(frames));
+		actl.extend_from_slice(&be32(0));	//Loop forever.
+		write_chunk(out, b"acTL", &actl);
+		ApngWriter{width: width, height: height, fps: fps, level: level, sequence: 0}
+	}
+	//Write rectangle of img as the next frame. The rectangle must be within the image.
+	pub fn write_frame<W:Write,I:ImageSource>(&mut self, out: &mut W, img: &I, x: usize, y: usize, w: usize,
+		h: usize)
+	{
+		let rect = ScaledView::new(img, x, y, w, h, 1).unwrap();
+		let (w, h) = rect.get_size();
+		let mut fctl = Vec::with_capacity(26);
+		fctl.extend_from_slice(&be32(self.sequence));
+		fctl.extend_from_slice(&be32(w as u32));
+		fctl.extend_from_slice(&be32(h as u32));
+		fctl.extend_from_slice(&be32(x as u32));
+		fctl.extend_from_slice(&be32(y as u32));
+		//Delay 1/fps seconds, no disposal, replace the rectangle.
+		fctl.extend_from_slice(&[0, 1, (self.fps >> 8) as u8, self.fps as u8, 0, 0]);
+		write_chunk(out, b"fcTL", &fctl);
+		//The first frame is also the default image, so it goes into IDAT.
+		let first = self.sequence == 0;
+		if first && (x, y, w, h) != (0, 0, self.width, self.height) { panic!("First APNG frame is partial"); }
+		let mut idat = IdatWriter::new(self.level, if first { None } else { Some(self.sequence + 1) });
+		write_image_data(out, &rect, None, &mut idat, self.level);
+		self.sequence = idat.sequence.unwrap_or(self.sequence + 1);
+	}
+	pub fn finish<W:Write>(self, out: &mut W)
+	{
+		write_iend(out);
+	}
+}
+
 #[link(name = "z")]
 extern
 {
-	fn adler32(crc: u32, buf: *const u8, len: u32) -> u32;
-	fn crc32(crc: u32, buf: *const u8, len: u32) -> u32;
+	pub(crate) fn crc32(crc: u32, buf: *const u8, len: u32) -> u32;
+	fn deflateInit_(strm: *mut ZStream, level: c_int, version: *const c_char, stream_size: c_int) -> c_int;
+	fn deflate(strm: *mut ZStream, flush: c_int) -> c_int;
+	fn deflateEnd(strm: *mut ZStream) -> c_int;
 }
 
@@ -0,0 +1,199 @@
+use ::root_path;
+use ::error::Error;
+use ::imagestate::ImageState;
+use ::mmapstate::MmapImageState;
+use ::scene::Scene;
+use ::scene_endpoint::{EventInfo, EventWriter, Rejected};
+use ::scene_events::notify_scene;
+use ::sceneconfig::{SceneConfig, lock_scene, scene_origin, scene_protected, scene_size};
+use ::scenes_endpoint::MAXPIXELS;
+use postgres::Connection;
+use postgres::rows::Rows;
+use std::cmp::{max, min};
+use std::fs::{remove_file, rename};
+
+//Write the history of scene between timestamps tstart and tend (inclusive) into image, in the same order as
+//events would have been written.
+pub fn replay_scene_range<I:ImageState>(conn: &Connection, scene: Scene, img: &mut I, tstart: i64, tend: i64)
+{
+	for row in conn.query("SELECT timestamp,color,x,y FROM scene_view WHERE sceneid=$1 AND timestamp>=$2 AND \
+		timestamp<=$3 ORDER BY timestamp, recordid", &[&scene, &tstart, &tend]).unwrap().iter() {
+		img.write_pixel(row.get(2), row.get(3), row.get(0), row.get(1));
+	}
+}
+
+//Write the entire history of scene into image.
+pub fn replay_scene<I:ImageState>(conn: &Connection, scene: Scene, img: &mut I)
+{
+	replay_scene_range(conn, scene, img, i64::min_value(), i64::max_value())
+}
+
+//Events of scene split into frames at fixed intervals.
+pub struct Timelapse
+{
+	events: Rows,
+	first: i64,
+	interval: i64,
+	frames: i64,
+}
+
+impl Timelapse
+{
+	//Events between start and end (inclusive). Without start or end, the timelapse runs from the first event or
+	//to the last.
+	pub fn new(conn: &Connection, scene: Scene, start: Option<i64>, end: Option<i64>, interval: i64) -> Timelapse
+	{
+		let tstart = start.unwrap_or(i64::min_value());
+		let tend = end.unwrap_or(i64::max_value());
+		let events = conn.query("SELECT timestamp,color,x,y FROM scene_view WHERE sceneid=$1 AND timestamp>=$2 \
+			AND timestamp<=$3 ORDER BY timestamp, recordid", &[&scene, &tstart, &tend]).unwrap();
+		let first = start.or(events.iter().next().map(|x|x.get(0))).unwrap_or(0);
+		let last = end.or(events.iter().last().map(|x|x.get(0))).unwrap_or(first);
+		let frames = max(last.saturating_sub(first), 0) / interval + 1;
+		Timelapse{events: events, first: first, interval: interval, frames: frames}
+	}
+	pub fn frames(&self) -> i64
+	{
+		self.frames
+	}
+	//Replay into img. After each frame, frame is called with the rectangle (x, y, w, h) changed since the previous
+	//frame, or None if nothing changed. Each frame shows the state at its time.
+	pub fn replay<I:ImageState,F>(&self, img: &mut I, mut frame: F) where F: FnMut(&I, Option<(usize, usize,
+		usize, usize)>)
+	{
+		let (w, h) = img.get_size();
+		let mut events = self.events.iter().peekable();
+		for i in 0..self.frames {
+			let fend = self.first.saturating_add(i.saturating_mul(self.interval));
+			let mut dirty: Option<(usize, usize, usize, usize)> = None;
+			while let Some(ts) = events.peek().map(|ev|{let ts: i64 = ev.get(0); ts}) {
+				if ts > fend { break; }
+				let ev = events.next().unwrap();
+				let (x, y): (i32, i32) = (ev.get(2), ev.get(3));
+				img.write_pixel(x, y, ts, ev.get(1));
+				if x >= 0 && y >= 0 && (x as usize) < w && (y as usize) < h {
+					let (x, y) = (x as usize, y as usize);
+					dirty = Some(match dirty {
+						Some((x0, y0, x1, y1)) => (min(x0, x), min(y0, y), max(x1, x), max(y1, y)),
+						None => (x, y, x, y)
+					});
+				}
+			}
+			frame(img, dirty.map(|(x0, y0, x1, y1)|(x0, y0, x1 - x0 + 1, y1 - y0 + 1)));
+		}
+	}
+}
+
+//Recompute the current state of scene from its history, and replace the old state with it.
+pub fn rebuild_scene(conn: &Connection, scene: Scene) -> Result<(), Error>
+{
+	let tname = format!("{}/currentstate/{}.tmp", root_path(), scene.as_inner());
+	let fname = format!("{}/currentstate/{}", root_path(), scene.as_inner());
+	conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+	//Writers wait until the new state is in place.
+	let (w, h) = lock_scene(conn, scene)?;
+	//The state file is not truncated on open, so get rid of any leftovers.
+	remove_file(&tname).ok();
+	{
+		let mut mmap = MmapImageState::new(&tname, w as usize, h as usize).unwrap();
+		replay_scene(conn, scene, &mut mmap);
+	}
+	rename(&tname, &fname).unwrap();
+	conn.execute("COMMIT", &[]).unwrap();
+	Ok(())
+}
+
+//Change the size of scene, moving everything in it by dx and dy. The history is not modified, only the origin it is
+//read relative to. Events that end up outside the scene are hidden, so they come back if the scene grows again.
+pub fn resize_scene(conn: &Connection, scene: Scene, w: u32, h: u32, dx: i32, dy: i32) -> Result<(), Error>
+{
+	if w == 0 || h == 0 || w.checked_mul(h).map(|x|x > MAXPIXELS).unwrap_or(true) {
+		return Err(Error::InvalidDimensions);
+	}
+	let (w, h) = (w as i32, h as i32);
+	let tname = format!("{}/currentstate/{}.resize", root_path(), scene.as_inner());
+	let fname = format!("{}/currentstate/{}", root_path(), scene.as_inner());
+	conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+	//Writers wait until the new state is in place.
+	let (ow, oh) = lock_scene(conn, scene)?;
+	let (ox, oy) = scene_origin(conn, scene);
+	let (xorigin, yorigin) = match (ox.checked_add(dx), oy.checked_add(dy)) {
+		(Some(x), Some(y)) => (x, y),
+		_ => return Err(Error::InvalidDimensions)
+	};
+	conn.execute("UPDATE scenes SET width=$1, height=$2, xorigin=$3, yorigin=$4 WHERE sceneid=$5", &[&w, &h,
+		&xorigin, &yorigin, &scene]).unwrap();
+	if dx != 0 || dy != 0 {
+		for p in scene_protected(conn, scene).iter() {
+			match p.region.translate(dx, dy) {
+				Some(r) => conn.execute("UPDATE scene_protected SET x=$1, y=$2, w=$3, h=$4 WHERE regionid=$5",
+					&[&r.x, &r.y, &r.w, &r.h, &p.id]).unwrap(),
+				None => conn.execute("DELETE FROM scene_protected WHERE regionid=$1", &[&p.id]).unwrap(),
+			};
+		}
+		let mut config = SceneConfig::load(conn, scene);
+		if config.regions.is_some() {
+			config.regions = config.regions.map(|x|x.iter().filter_map(|r|r.translate(dx, dy)).collect());
+			config.store(conn, scene);
+		}
+	}
+	remove_file(&tname).ok();
+	{
+		//Cells that were in the scene before keep their state, the rest come from history.
+		let (dx64, dy64) = (dx as i64, dy as i64);
+		let old = MmapImageState::new(&fname, ow as usize, oh as usize).unwrap();
+		let mmap = MmapImageState::new(&tname, w as usize, h as usize).unwrap();
+		mmap.copy_from(&old, dx, dy);
+		for row in conn.query("SELECT timestamp,color,x,y FROM scene_view WHERE sceneid=$1 AND x<$2 AND y<$3 AND \
+			NOT (x::bigint>=$4 AND y::bigint>=$5 AND x::bigint<$4+$6 AND y::bigint<$5+$7) ORDER BY timestamp, \
+			recordid", &[&scene, &w, &h, &dx64, &dy64, &(ow as i64), &(oh as i64)]).unwrap().iter() {
+			mmap.write_pixel(row.get(2), row.get(3), row.get(0), row.get(1));
+		}
+	}
+	rename(&tname, &fname).unwrap();
+	conn.execute("COMMIT", &[]).unwrap();
+	notify_scene(conn, scene);
+	Ok(())
+}
+
+//Copy events of source into scene as application appid, moved by dx and dy in space and dt in time. The events
+//are checked against the palette, regions and protected regions, but not the open and close times or cooldown.
+//Events that end up outside the scene are skipped. Returns the number of events copied and skipped, and the ones
+//not allowed.
+pub fn merge_scene(conn: &Connection, scene: Scene, source: Scene, appid: i32, dx: i32, dy: i32, dt: i64) ->
+	Result<(u64, u64, Rejected), Error>
+{
+	if source == scene { return Err(Error::BadFormField("merge".to_owned())); }
+	let exists: i64 = conn.query("SELECT COUNT(sceneid) FROM scenes WHERE sceneid=$1", &[&source]).unwrap().iter().
+		next().unwrap().get(0);
+	if exists == 0 { return Err(Error::SceneNotFound); }
+	let events = conn.query("SELECT timestamp,username,color,x,y FROM scene_view WHERE sceneid=$1 ORDER BY \
+		timestamp, recordid", &[&source]).unwrap();
+	let mut writer = EventWriter::new_copy(scene, appid);
+	let rejected = Rejected::default();
+	let mut copied = 0;
+	let mut clipped = 0;
+	conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+	writer.lock(conn)?;
+	let (w, h) = scene_size(conn, scene)?;
+	for row in events.iter() {
+		let ts: i64 = row.get(0);
+		let (x, y): (i32, i32) = (row.get(3), row.get(4));
+		let (x, y) = (x as i64 + dx as i64, y as i64 + dy as i64);
+		if x < 0 || y < 0 || x >= w as i64 || y >= h as i64 { clipped += 1; continue; }
+		let ev = EventInfo{
+			ts: ts.saturating_add(dt),
+			username: row.get(1),
+			color: row.get(2),
+			x: x as i32,
+			y: y as i32,
+		};
+		match writer.write(conn, &ev) {
+			Ok(_) => copied += 1,
+			Err(x) => rejected.add(x)
+		}
+	}
+	notify_scene(conn, scene);
+	conn.execute("COMMIT", &[]).unwrap();
+	Ok((copied, clipped, rejected))
+}
@@ -41,14 +41,13 @@ fn unpermute(s: &[u8]) -> i32
 {
 	let seed: Vec<u8> = get_scene_key();
 	let mut n = 0;
-	for i in 0..6 {
-		let c = s[i];
-		n = n | ((c - match s[i] {
+	for (i, &c) in s.iter().enumerate().take(6) {
+		n |= ((c - match c {
 			65...90 => 65,
 			97...122 => 97,
 			50...55 => 24,
 			_ => c,
-		}) as u32) << 5 * i;
+		}) as u32) << (5 * i);
 	}
 	let l = n >> 15;
 	let r = n & 0x7FFF;
@@ -59,7 +58,7 @@ fn unpermute(s: &[u8]) -> i32
 	((l << 15) | r) as i32
 }
 
-#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
 pub struct Scene(i32);
 
 impl Scene
@@ -1,31 +1,45 @@
 use ::{db_connect, sink_put, sink_put_remaining, root_path};
 use ::authentication::AuthenticationInfo;
 use ::cors::SendFileAsWithCors;
-use ::error::Error;
+use ::error::{Error, retry_after};
 use ::json::{JsonToken, JsonStream, escape_json_string};
 use ::lsmv::scene_get_lsmv as _scene_get_lsmv;
+use ::imagestate::{ImageSource, MemoryImageState};
 use ::mmapstate::MmapImageState;
-use ::png::{scan_image_as_png, scan_image_as_png_size};
+use ::replay::{merge_scene, rebuild_scene, replay_scene_range, resize_scene, Timelapse};
+use ::png::{scan_image_as_png, ApngWriter, ScaledView};
+use ::gif::GifWriter;
+use ::scene_events::{notify_scene, listen_scene, wait_scene, WaitSlot};
 use ::scene::Scene;
+use ::scenes_endpoint::MAXPIXELS;
+use ::sceneconfig::{ProtectedRegion, Region, SceneConfig, format_palette, lock_scene, scene_origin, scene_palette,
+	scene_protected, scene_size, server_time};
 use ::xml::{XmlSerializer, XmlOutputStream};
 use ::xml::xhtml::Html;
 use ::xml::CONTENT_TYPE_XHTML;
-use rocket::request::{FromRequest, FromForm, Form, FormItems, Request};
+use postgres::Connection;
+use postgres::types::ToSql;
+use rocket::request::{FromRequest, FromForm, FromParam, Form, FormItems, Request};
 use rocket::outcome::Outcome;
 use rocket::response::Responder;
-use rocket::http::Status;
-use rocket::http::uri::Uri;
+use rocket::http::{RawStr, Status};
+use rocket::http::uri::URI as Uri;
 use rocket::Data;
 use time::Timespec;
 use time::at_utc;
 use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp::{max, min};
+use std::collections::HashSet;
 use std::fmt::Write as FmtWrite;
-use std::io::Write as IoWrite;
-use std::fs::{File,rename};
 use std::io::Cursor;
 use std::io::Read as IoRead;
 use std::ops::Deref;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+//Maximum size of event upload in bytes.
+const MAX_EVENT_UPLOAD: u64 = 4 * 1024 * 1024;
 
 pub struct EventInfo
 {
@@ -38,8 +52,11 @@ pub struct EventInfo
 
 pub struct GetBounds
 {
-	start: Option<i64>,
-	end: Option<i64>
+	pub start: Option<i64>,
+	pub end: Option<i64>,
+	wait: Option<u64>,
+	limit: Option<i64>,
+	cursor: Option<String>,
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for GetBounds
@@ -48,16 +65,152 @@ impl<'a, 'r> FromRequest<'a, 'r> for GetBounds
 	fn from_request(request: &'a Request<'r>) -> Outcome<GetBounds, (Status, ()), ()> {
 		let mut start = None;
 		let mut end = None;
+		let mut wait = None;
+		let mut limit = None;
+		let mut cursor = None;
 		for p in request.uri().query().unwrap_or("").split("&").map(|i|Uri::percent_decode(i.as_bytes()).
 			unwrap_or(Cow::Borrowed(""))) {
 			if p.starts_with("since=") { i64::from_str(&p[6..]).map(|x|start = Some(x)).ok(); }
 			if p.starts_with("until=") { i64::from_str(&p[6..]).map(|x|end = Some(x)).ok(); }
+			if p.starts_with("at=") { i64::from_str(&p[3..]).map(|x|end = Some(x)).ok(); }
+			if p.starts_with("wait=") { u64::from_str(&p[5..]).map(|x|wait = Some(x)).ok(); }
+			if p.starts_with("limit=") { i64::from_str(&p[6..]).map(|x|limit = Some(max(x, 1))).ok(); }
+			if p.starts_with("cursor=") { cursor = Some((&p[7..]).to_owned()); }
+		}
+		Outcome::Success(GetBounds{start, end, wait, limit, cursor})
+	}
+}
+
+//Events as d with their scene as s. Position conditions against this, unlike scene_view, can use the index on
+//stored coordinates. Events are at d.x+s.xorigin, d.y+s.yorigin.
+pub const EVENTS_WITH_ORIGIN: &'static str = "scene_data d JOIN scenes s ON d.sceneid=s.sceneid";
+
+//Which events to list. Users and colors are matched against any of those given, the rectangle is inclusive.
+pub struct EventFilter
+{
+	users: Option<Vec<String>>,
+	colors: Option<Vec<i32>>,
+	x0: i32,
+	y0: i32,
+	x1: i32,
+	y1: i32,
+}
+
+//Default passes every event.
+impl Default for EventFilter
+{
+	fn default() -> EventFilter
+	{
+		EventFilter{
+			users: None,
+			colors: None,
+			x0: i32::min_value(),
+			y0: i32::min_value(),
+			x1: i32::max_value(),
+			y1: i32::max_value(),
+		}
+	}
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for EventFilter
+{
+	type Error = ();
+	fn from_request(request: &'a Request<'r>) -> Outcome<EventFilter, (Status, ()), ()> {
+		let mut users = Vec::new();
+		let mut colors = Vec::new();
+		let all = EventFilter::default();
+		let (mut x0, mut y0, mut x1, mut y1) = (all.x0, all.y0, all.x1, all.y1);
+		for p in request.uri().query().unwrap_or("").split("&").map(|i|Uri::percent_decode(i.as_bytes()).
+			unwrap_or(Cow::Borrowed(""))) {
+			if p.starts_with("user=") { users.push((&p[5..]).to_owned()); }
+			if p.starts_with("color=") { i32::from_str(&p[6..]).map(|x|colors.push(x & 0xFFFFFF)).ok(); }
+			if p.starts_with("x0=") { i32::from_str(&p[3..]).map(|x|x0 = x).ok(); }
+			if p.starts_with("y0=") { i32::from_str(&p[3..]).map(|x|y0 = x).ok(); }
+			if p.starts_with("x1=") { i32::from_str(&p[3..]).map(|x|x1 = x).ok(); }
+			if p.starts_with("y1=") { i32::from_str(&p[3..]).map(|x|y1 = x).ok(); }
+		}
+		Outcome::Success(EventFilter{
+			users: if users.len() > 0 { Some(users) } else { None },
+			colors: if colors.len() > 0 { Some(colors) } else { None },
+			x0: x0,
+			y0: y0,
+			x1: x1,
+			y1: y1,
+		})
+	}
+}
+
+impl EventFilter
+{
+	//SQL condition for events in EVENTS_WITH_ORIGIN passing the filter, with params() as parameters starting from
+	//$first. The rectangle is moved to stored coordinates so the index on them can be used, and it excludes the
+	//same events as scene_view does.
+	pub fn condition(first: usize) -> String
+	{
+		format!("(${0}::text[] IS NULL OR d.username=ANY(${0})) AND (${1}::integer[] IS NULL OR (d.color & \
+			16777215)=ANY(${1})) AND d.x>=GREATEST(${2}::integer,0)-s.xorigin::bigint AND \
+			d.y>=GREATEST(${3}::integer,0)-s.yorigin::bigint AND d.x<=${4}::integer-s.xorigin::bigint AND \
+			d.y<=${5}::integer-s.yorigin::bigint", first, first + 1, first + 2, first + 3, first + 4, first + 5)
+	}
+	pub fn params(&self) -> [&ToSql; 6]
+	{
+		[&self.users, &self.colors, &self.x0, &self.y0, &self.x1, &self.y1]
+	}
+}
+
+//Part of scene to show in PNG.
+pub struct PngView
+{
+	x: usize,
+	y: usize,
+	w: Option<usize>,
+	h: Option<usize>,
+	scale: usize,
+	level: i32,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PngView
+{
+	type Error = ();
+	fn from_request(request: &'a Request<'r>) -> Outcome<PngView, (Status, ()), ()> {
+		let mut view = PngView{x: 0, y: 0, w: None, h: None, scale: 1, level: 6};
+		for p in request.uri().query().unwrap_or("").split("&").map(|i|Uri::percent_decode(i.as_bytes()).
+			unwrap_or(Cow::Borrowed(""))) {
+			if p.starts_with("x=") { usize::from_str(&p[2..]).map(|x|view.x = x).ok(); }
+			if p.starts_with("y=") { usize::from_str(&p[2..]).map(|x|view.y = x).ok(); }
+			if p.starts_with("w=") { usize::from_str(&p[2..]).map(|x|view.w = Some(x)).ok(); }
+			if p.starts_with("h=") { usize::from_str(&p[2..]).map(|x|view.h = Some(x)).ok(); }
+			if p.starts_with("scale=") { usize::from_str(&p[6..]).map(|x|view.scale = x).ok(); }
+			if p.starts_with("level=") { i32::from_str(&p[6..]).map(|x|view.level = x).ok(); }
+		}
+		Outcome::Success(view)
+	}
+}
+
+//Timing of timelapse animation.
+pub struct TimelapseView
+{
+	interval: i64,
+	fps: u16,
+	level: i32,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for TimelapseView
+{
+	type Error = ();
+	fn from_request(request: &'a Request<'r>) -> Outcome<TimelapseView, (Status, ()), ()> {
+		let mut view = TimelapseView{interval: 60000, fps: 10, level: 6};
+		for p in request.uri().query().unwrap_or("").split("&").map(|i|Uri::percent_decode(i.as_bytes()).
+			unwrap_or(Cow::Borrowed(""))) {
+			if p.starts_with("interval=") { i64::from_str(&p[9..]).map(|x|view.interval = x).ok(); }
+			if p.starts_with("fps=") { u16::from_str(&p[4..]).map(|x|view.fps = x).ok(); }
+			if p.starts_with("level=") { i32::from_str(&p[6..]).map(|x|view.level = x).ok(); }
 		}
-		Outcome::Success(GetBounds{start, end})
+		Outcome::Success(view)
 	}
 }
 
-fn format_row(target: &mut String, row: &EventInfo)
+pub fn format_row(target: &mut String, row: &EventInfo)
 {
 	let eusername = escape_json_string(&row.username);
 	write!(target, r#"{{"ts":{},"u":"{}","c":{},"x":{},"y":{}}}"#, row.ts, eusername, row.color,
@@ -65,6 +218,8 @@ fn format_row(target: &mut String, row: &EventInfo)
 }
 
 const SCENE_METHODS: &'static str = "HEAD, GET";
+//Maximum time to wait for new events in long poll (milliseconds).
+const MAX_WAIT: u64 = 60000;
 const SCENE_HEADERS: &'static str = "";
 
 pub fn scene_options(scene: Scene) -> Result<impl Responder<'static>, Error>
@@ -78,29 +233,70 @@ pub fn scene_options(scene: Scene) -> Result<impl Responder<'static>, Error>
 	})
 }
 
-pub fn scene_get(scene: Scene, range: GetBounds) -> Result<impl Responder<'static>, Error>
+//Position in events of scene, after event with timestamp and recordid.
+pub fn encode_cursor(ts: i64, recordid: i32) -> String
+{
+	format!("{:016x}{:08x}", ts as u64, recordid as u32)
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<(i64, i32), Error>
+{
+	let bad = ||Error::BadFormField("cursor".to_owned());
+	//from_str_radix would also take a sign.
+	if cursor.len() != 24 || !cursor.chars().all(|c|c.is_digit(16)) { return Err(bad()); }
+	let ts = u64::from_str_radix(&cursor[..16], 16).map_err(|_|bad())?;
+	let recordid = u32::from_str_radix(&cursor[16..], 16).map_err(|_|bad())?;
+	Ok((ts as i64, recordid as i32))
+}
+
+pub fn scene_get(scene: Scene, range: GetBounds, filter: EventFilter) -> Result<impl Responder<'static>, Error>
 {
 	let conn = db_connect();
-	let (w, h) = if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1", &[&scene]).
-		unwrap().iter().next() {
-		let w: i32 = row.get(0);
-		let h: i32 = row.get(1);
-		(w, h)
-	} else {
-		return Err(Error::SceneNotFound);
-	};
+	let (w, h) = scene_size(&conn, scene)?;
 	let tstart = range.start.unwrap_or(i64::min_value());
 	let tend = range.end.unwrap_or(i64::max_value());
-	let mut retval = Vec::new();
-	for row in conn.query("SELECT timestamp,username,color,x,y FROM scene_data WHERE sceneid=$1 AND timestamp>=$2 \
-		AND timestamp <= $3 ORDER BY timestamp, recordid", &[&scene, &tstart, &tend]).unwrap().iter() {
-		retval.push(EventInfo {
-			ts: row.get(0),
-			username: row.get(1),
-			color: row.get(2),
-			x: row.get(3),
-			y: row.get(4),
-		});
+	//Without cursor, start before every event.
+	let (cts, crecord) = match range.cursor {
+		Some(ref cursor) => decode_cursor(cursor)?,
+		None => (i64::min_value(), i32::min_value())
+	};
+	let last = Cell::new(None);
+	let query = format!("SELECT d.timestamp,d.username,d.color,d.x+s.xorigin,d.y+s.yorigin,d.recordid FROM {} \
+		WHERE d.sceneid=$1 AND d.timestamp>=$2 AND d.timestamp <= $3 AND (d.timestamp, d.recordid) > ($4, $5) AND \
+		{} ORDER BY d.timestamp, d.recordid LIMIT $6", EVENTS_WITH_ORIGIN, EventFilter::condition(7));
+	let mut params: Vec<&ToSql> = Vec::new();
+	params.extend_from_slice(&[&scene, &tstart, &tend, &cts, &crecord, &range.limit]);
+	params.extend_from_slice(&filter.params());
+	let fetch = ||{
+		let mut retval = Vec::new();
+		//NULL limit means no limit.
+		for row in conn.query(&query, &params).unwrap().iter() {
+			retval.push(EventInfo {
+				ts: row.get(0),
+				username: row.get(1),
+				color: row.get(2),
+				x: row.get(3),
+				y: row.get(4),
+			});
+			let recordid: i32 = row.get(5);
+			last.set(Some((row.get(0), recordid)));
+		}
+		retval
+	};
+	let mut retval;
+	if let Some(wait) = range.wait {
+		let _slot = WaitSlot::acquire()?;
+		//Long poll: Listen before the first query, so events written in between are not missed.
+		let deadline = Instant::now() + Duration::from_millis(min(wait, MAX_WAIT));
+		listen_scene(&conn, scene);
+		retval = fetch();
+		while retval.len() == 0 {
+			let now = Instant::now();
+			if now >= deadline { break; }
+			if wait_scene(&conn, deadline - now) { retval = fetch(); }
+		}
+	} else {
+		retval = fetch();
 	}
 	let mut out = String::new();
 	out.push_str(r#"{"data":["#);
@@ -110,8 +306,17 @@ pub fn scene_get(scene: Scene, range: GetBounds) -> Result<impl Responder<'stati
 		format_row(&mut out, i);
 		first = false;
 	}
-	write!(out, r#"],"width":{},"height":{}}}"#, w, h).unwrap();
-	out.push('\n');
+	write!(out, r#"],"width":{},"height":{},"palette":"#, w, h).unwrap();
+	format_palette(&mut out, &scene_palette(&conn, scene));
+	//Full page means there may be more. Long polls always continue from the last event.
+	match (range.limit, last.get()) {
+		(Some(limit), Some((ts, recordid))) if retval.len() as i64 >= limit =>
+			write!(out, r#","next":"{}""#, encode_cursor(ts, recordid)).unwrap(),
+		(_, Some((ts, recordid))) if range.wait.is_some() =>
+			write!(out, r#","next":"{}""#, encode_cursor(ts, recordid)).unwrap(),
+		_ => out.push_str(r#","next":null"#)
+	};
+	out.push_str("}\n");
 	//Return with headers.
 	Ok(SendFileAsWithCors{
 		content_type: "application/json",
@@ -122,7 +327,7 @@ pub fn scene_get(scene: Scene, range: GetBounds) -> Result<impl Responder<'stati
 }
 
 //Assumes last token was StartObject.
-fn parse_one_event<R:IoRead>(stream: &mut JsonStream<R>) -> Result<EventInfo, String>
+pub fn parse_one_event<R:IoRead>(stream: &mut JsonStream<R>) -> Result<EventInfo, String>
 {
 	let mut ts = None;
 	let mut username = None;
@@ -194,6 +399,152 @@ fn parse_event_stream<R:IoRead,F>(stream: &mut R, sink: &F) -> Result<u64, Strin
 	Ok(events)
 }
 
+//Milliseconds until user can write to scene again.
+fn cooldown_remaining(conn: &Connection, scene: Scene, username: &str, cooldown: i64, now: i64) -> i64
+{
+	conn.query("SELECT last FROM scene_cooldowns WHERE sceneid=$1 AND username=$2", &[&scene, &username]).unwrap().
+		iter().next().map(|row|{
+		let last: i64 = row.get(0);
+		max(last.saturating_add(cooldown) - now, 0)
+	}).unwrap_or(0)
+}
+
+//Writes events into scene, both to the current state and to the database.
+pub struct EventWriter
+{
+	scene: Scene,
+	appid: i32,
+	origin: (i32, i32),
+	mmap: Option<MmapImageState>,
+	config: SceneConfig,
+	palette: Option<HashSet<i32>>,
+	protected: Vec<ProtectedRegion>,
+	//Whether events are placed by users now, so the open and close times and cooldown apply.
+	interactive: bool,
+}
+
+impl EventWriter
+{
+	//Events are written by application appid. Nothing can be written before lock.
+	pub fn new(scene: Scene, appid: i32) -> EventWriter
+	{
+		EventWriter{
+			scene: scene,
+			appid: appid,
+			origin: (0, 0),
+			mmap: None,
+			config: SceneConfig::default(),
+			palette: None,
+			protected: Vec::new(),
+			interactive: true,
+		}
+	}
+	//Like new, but for copying existing events. Only the palette, regions and protected regions are checked, and
+	//cooldowns are not touched.
+	pub fn new_copy(scene: Scene, appid: i32) -> EventWriter
+	{
+		EventWriter{interactive: false, ..EventWriter::new(scene, appid)}
+	}
+	//Lock the scene until end of the current transaction, and pick up its current state and config. As writers
+	//hold the lock until commit, events become visible in order of record number.
+	pub fn lock(&mut self, conn: &Connection) -> Result<(), Error>
+	{
+		let (w, h) = lock_scene(conn, self.scene)?;
+		//Rebuilds and resizes replace the state file, so open it again.
+		self.mmap = Some(MmapImageState::new(format!("{}/currentstate/{}", root_path(), self.scene.as_inner()),
+			w as usize, h as usize).unwrap());
+		self.origin = scene_origin(conn, self.scene);
+		self.config = SceneConfig::load(conn, self.scene);
+		self.palette = self.config.palette.as_ref().map(|x|x.iter().cloned().collect());
+		self.protected = scene_protected(conn, self.scene);
+		Ok(())
+	}
+	//Fails without writing anything if the event is not allowed in the scene.
+	pub fn write(&self, conn: &Connection, ev: &EventInfo) -> Result<(), Error>
+	{
+		let mmap = self.mmap.as_ref().expect("EventWriter used without lock");
+		let now = server_time();
+		if self.interactive && (self.config.open.map(|x|now < x).unwrap_or(false) || self.config.close.
+			map(|x|now >= x).unwrap_or(false)) {
+			return Err(Error::SceneClosed);
+		}
+		if let Some(ref palette) = self.palette {
+			if !palette.contains(&ev.color) { return Err(Error::ColorNotInPalette(ev.color)); }
+		}
+		if let Some(ref regions) = self.config.regions {
+			if !regions.iter().any(|r|r.contains(ev.x, ev.y)) { return Err(Error::OutsideRegions); }
+		}
+		if self.protected.iter().any(|p|p.region.contains(ev.x, ev.y) && !p.allows(self.appid, &ev.username)) {
+			return Err(Error::ProtectedRegion);
+		}
+		//History is stored relative to the origin, and far enough outside the scene it can not be.
+		let (sx, sy) = match (ev.x.checked_sub(self.origin.0), ev.y.checked_sub(self.origin.1)) {
+			(Some(x), Some(y)) => (x, y),
+			_ => return Err(Error::OutsideRegions)
+		};
+		//This is last, so rejected events do not start the cooldown.
+		if let (true, Some(cooldown)) = (self.interactive, self.config.cooldown) {
+			let ms = cooldown * 1000;
+			if conn.query("INSERT INTO scene_cooldowns (sceneid,username,last) VALUES ($1,$2,$3) ON CONFLICT \
+				(sceneid,username) DO UPDATE SET last=$3 WHERE scene_cooldowns.last<=$4 RETURNING last",
+				&[&self.scene, &ev.username, &now, &(now - ms)]).unwrap().len() == 0 {
+				return Err(Error::Cooldown(cooldown_remaining(conn, self.scene, &ev.username, ms, now)));
+			}
+		}
+		mmap.write_pixel(ev.x, ev.y, ev.ts, ev.color);
+		//Use prepared statement to improve performance.
+		conn.prepare_cached("INSERT INTO scene_data (sceneid,timestamp,username,color,x,y) VALUES \
+			($1,$2,$3,$4,$5,$6) ON CONFLICT DO NOTHING").unwrap().execute(&[&self.scene, &ev.ts, &ev.username,
+			&ev.color, &sx, &sy]).unwrap();
+		Ok(())
+	}
+}
+
+//Counts of events skipped when writing many at once.
+#[derive(Default)]
+pub struct Rejected
+{
+	config: Cell<u64>,
+	protected: Cell<u64>,
+	cooldown: Cell<u64>,
+	//Longest wait (milliseconds) until cooldown is over.
+	retry: Cell<i64>,
+}
+
+impl Rejected
+{
+	//Count error from EventWriter::write.
+	pub fn add(&self, err: Error)
+	{
+		match err {
+			Error::ProtectedRegion => self.protected.set(self.protected.get() + 1),
+			Error::Cooldown(ms) => {
+				self.cooldown.set(self.cooldown.get() + 1);
+				self.retry.set(max(self.retry.get(), ms));
+			},
+			_ => self.config.set(self.config.get() + 1),
+		}
+	}
+	pub fn total(&self) -> u64
+	{
+		self.config.get() + self.protected.get() + self.cooldown.get()
+	}
+	//Append lines telling what was rejected.
+	pub fn describe(&self, message: &mut String)
+	{
+		if self.config.get() > 0 {
+			message.push_str(&format!("Rejected {} event(s) not allowed by scene config\n", self.config.get()));
+		}
+		if self.protected.get() > 0 {
+			message.push_str(&format!("Rejected {} event(s) in protected regions\n", self.protected.get()));
+		}
+		if self.cooldown.get() > 0 {
+			message.push_str(&format!("Rejected {} event(s) because of cooldown, retry after {} second(s)\n",
+				self.cooldown.get(), retry_after(self.retry.get())));
+		}
+	}
+}
+
 const SCENE_EDIT_METHODS: &'static str = "PUT, POST, DELETE";
 const SCENE_EDIT_HEADERS: &'static str = "api-origin, api-key, content-type";
 
@@ -211,41 +562,43 @@ pub fn scene_edit_put(scene: Scene, auth: AuthenticationInfo, upload: Data) -> R
 {
 	let mut conn = db_connect();
 
-	match auth.check_write(&mut conn, scene) {
-		Ok(_) => (),
+	let appid = match auth.check_write(&mut conn, scene) {
+		Ok(x) => x,
 		Err(false) => return Err(sink_put(upload, Error::SceneNotFound)),	//Don't barf.
 		Err(true) => return Err(sink_put(upload, Error::InvalidOrigin)),	//Don't barf.
 	};
 
-	//Grab width and height of scene.
-	let (w, h) = if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1", &[&scene]).
-		unwrap().iter().next() {
-		let w: i32 = row.get(0);
-		let h: i32 = row.get(1);
-		(w, h)
-	} else {
-		return Err(sink_put(upload, Error::SceneNotFound));	//Don't barf.
-	};
+	//Read the whole upload before locking, so slow clients do not hold the scene.
+	let mut upload = upload.open();
+	let mut upbuf = Vec::new();
+	(&mut upload).take(MAX_EVENT_UPLOAD + 1).read_to_end(&mut upbuf).unwrap();
+	if upbuf.len() as u64 > MAX_EVENT_UPLOAD {
+		return Err(sink_put_remaining(upload, Error::EventsTooBig));
+	}
+	let evlist = RefCell::new(Vec::new());
+	let events = parse_event_stream(&mut &upbuf[..], &|ev|evlist.borrow_mut().push(ev)).
+		map_err(|x|Error::BadEventStream(x))?;
 
-	//Use prepared statement to improve performance.
-	let mmap = MmapImageState::new(format!("{}/currentstate/{}", root_path(), scene.as_inner()), w as usize, h
-		as usize).unwrap();
-	let stmt = conn.prepare("INSERT INTO scene_data (sceneid,timestamp,username,color,x,y) VALUES \
-		($1,$2,$3,$4,$5,$6) ON CONFLICT DO NOTHING").unwrap();
+	let mut writer = EventWriter::new(scene, appid);
+	//Events that are not allowed are skipped, the rest still get written.
+	let rejected = Rejected::default();
 	conn.execute("BEGIN TRANSACTION", &[]).unwrap();
-	let mut upload = upload.open();
-	let events = match parse_event_stream(&mut upload, &|ev|{
-			mmap.write_pixel(ev.x, ev.y, ev.ts, ev.color);
-			stmt.execute(&[&scene, &ev.ts, &ev.username, &ev.color, &ev.x, &ev.y]).unwrap();
-		}).map_err(|x|Error::BadEventStream(x)) {
-		Ok(x) => x,
-		Err(x) => return Err(sink_put_remaining(upload, x))
-	};
+	writer.lock(&conn)?;
+	for ev in evlist.borrow().iter() {
+		if let Err(x) = writer.write(&conn, ev) { rejected.add(x); }
+	}
+	notify_scene(&conn, scene);
 	conn.execute("COMMIT", &[]).unwrap();
+	//Nothing written because of cooldown is the same as single write hitting it.
+	if events == rejected.total() && rejected.cooldown.get() > 0 {
+		return Err(Error::Cooldown(rejected.retry.get()));
+	}
+	let mut message = format!("Wrote {} event(s)\n", events - rejected.total());
+	rejected.describe(&mut message);
 	//Ok.
 	Ok(SendFileAsWithCors{
 		content_type: "text/plain",
-		content: format!("Wrote {} event(s)\n", events).into_bytes(),
+		content: message.into_bytes(),
 		methods: SCENE_EDIT_METHODS,
 		headers: SCENE_EDIT_HEADERS,
 	})
@@ -270,6 +623,65 @@ pub enum ScenePostForm
 	Event(EventInfo),
 	Grant(String),
 	Ungrant(String),
+	Revert(String, Option<i64>, Option<i64>),
+	Rebuild,
+	Palette(Option<Vec<i32>>),
+	Protect(Region, Vec<String>, Vec<String>),
+	Unprotect(i32),
+	Resize(u32, u32, i32, i32),
+	Metadata(Option<String>, Option<String>, Option<bool>),
+	Merge(Scene, i32, i32, i64),
+}
+
+//Comma-separated list of colors, empty for no palette.
+fn parse_palette(x: &str) -> Result<Option<Vec<i32>>, Error>
+{
+	if x.trim() == "" { return Ok(None); }
+	let mut palette = Vec::new();
+	for c in x.split(',') {
+		let c = checkpos(i32::from_str(c.trim()), "palette")?;
+		if c > 0xFFFFFF { return Err(Error::BadFormField("palette".to_owned())); }
+		if !palette.contains(&c) { palette.push(c); }
+	}
+	Ok(Some(palette))
+}
+
+//Rectangle as x,y,w,h.
+fn parse_rectangle(x: &str) -> Result<Region, Error>
+{
+	let mut v = Vec::new();
+	for i in x.split(',') { v.push(checkpos(i32::from_str(i.trim()), "protect")?); }
+	if v.len() != 4 || v[2] == 0 || v[3] == 0 { return Err(Error::BadFormField("protect".to_owned())); }
+	Ok(Region{x: v[0], y: v[1], w: v[2], h: v[3]})
+}
+
+//Offset for moving the contents of scene.
+fn parse_offset(x: Option<String>, name: &str) -> Result<i32, Error>
+{
+	match x {
+		Some(x) => match i32::from_str(&x) {
+			Ok(x) if x >= -(MAXPIXELS as i32) && x <= MAXPIXELS as i32 => Ok(x),
+			_ => Err(Error::BadFormField(name.to_owned()))
+		},
+		None => Ok(0)
+	}
+}
+
+fn parse_bool(x: &str, name: &str) -> Result<bool, Error>
+{
+	match x {
+		"true" | "1" => Ok(true),
+		"false" | "0" => Ok(false),
+		_ => Err(Error::BadFormField(name.to_owned()))
+	}
+}
+
+fn parse_optional_ts(x: Option<String>, name: &str) -> Result<Option<i64>, Error>
+{
+	match x {
+		Some(x) => i64::from_str(&x).map(|x|Some(x)).map_err(|_|Error::BadFormField(name.to_owned())),
+		None => Ok(None)
+	}
 }
 
 impl<'r> FromForm<'r> for ScenePostForm
@@ -284,8 +696,28 @@ impl<'r> FromForm<'r> for ScenePostForm
 		let mut color = None;
 		let mut x = None;
 		let mut y = None;
+		let mut revert = None;
+		let mut since = None;
+		let mut until = None;
+		let mut rebuild = false;
+		let mut palette = None;
+		let mut protect = None;
+		let mut allow = Vec::new();
+		let mut allowuser = Vec::new();
+		let mut unprotect = None;
+		let mut width = None;
+		let mut height = None;
+		let mut dx = None;
+		let mut dy = None;
+		let mut name = None;
+		let mut description = None;
+		let mut public = None;
+		let mut merge = None;
+		let mut dt = None;
+		let mut fields = 0;
 		for (key, value) in it {
 			let val = value.url_decode().map_err(|_|Error::BadFormField(key.as_str().to_owned()))?;
+			fields += 1;
 			match key.as_str() {
 				"a" => grant = Some(val),
 				"d" => ungrant = Some(val),
@@ -294,10 +726,96 @@ impl<'r> FromForm<'r> for ScenePostForm
 				"c" => color = Some(val),
 				"x" => x = Some(val),
 				"y" => y = Some(val),
+				"r" => revert = Some(val),
+				"since" => since = Some(val),
+				"until" => until = Some(val),
+				"rebuild" => rebuild = true,
+				"palette" => palette = Some(val),
+				"protect" => protect = Some(val),
+				"allow" => allow.push(val),
+				"allowuser" => allowuser.push(val),
+				"unprotect" => unprotect = Some(val),
+				"width" => width = Some(val),
+				"height" => height = Some(val),
+				"dx" => dx = Some(val),
+				"dy" => dy = Some(val),
+				"name" => name = Some(val),
+				"description" => description = Some(val),
+				"public" => public = Some(val),
+				"merge" => merge = Some(val),
+				"dt" => dt = Some(val),
 				name if strict => return Err(Error::BadFormField(name.to_owned())),
-				_ => {}
+				_ => fields -= 1
 			};
 		}
+		//Rebuild takes no other fields.
+		if rebuild {
+			if fields != 1 { return Err(Error::BadFormField("invalid combination".to_string())); }
+			return Ok(ScenePostForm::Rebuild);
+		}
+		//Palette takes no other fields.
+		if let Some(palette) = palette {
+			if fields != 1 { return Err(Error::BadFormField("invalid combination".to_string())); }
+			return Ok(ScenePostForm::Palette(parse_palette(&palette)?));
+		}
+		//Protect takes any number of allow and allowuser.
+		if let Some(protect) = protect {
+			if fields != 1 + allow.len() + allowuser.len() {
+				return Err(Error::BadFormField("invalid combination".to_string()));
+			}
+			return Ok(ScenePostForm::Protect(parse_rectangle(&protect)?, allow, allowuser));
+		}
+		if allow.len() > 0 || allowuser.len() > 0 {
+			return Err(Error::BadFormField("invalid combination".to_string()));
+		}
+		//Unprotect takes no other fields.
+		if let Some(unprotect) = unprotect {
+			if fields != 1 { return Err(Error::BadFormField("invalid combination".to_string())); }
+			return Ok(ScenePostForm::Unprotect(i32::from_str(&unprotect).map_err(|_|
+				Error::BadFormField("unprotect".to_owned()))?));
+		}
+		//Metadata fields can be combined with each other, but nothing else.
+		if name.is_some() || description.is_some() || public.is_some() {
+			if fields != name.iter().count() + description.iter().count() + public.iter().count() {
+				return Err(Error::BadFormField("invalid combination".to_string()));
+			}
+			let public = match public { Some(x) => Some(parse_bool(&x, "public")?), None => None };
+			return Ok(ScenePostForm::Metadata(name, description, public));
+		}
+		//Merge takes optionally dx, dy and dt.
+		if let Some(merge) = merge {
+			if fields != 1 + dx.iter().count() + dy.iter().count() + dt.iter().count() {
+				return Err(Error::BadFormField("invalid combination".to_string()));
+			}
+			return Ok(ScenePostForm::Merge(Scene::from_param(RawStr::from_str(&merge)).map_err(|_|
+				Error::BadFormField("merge".to_owned()))?, parse_offset(dx, "dx")?, parse_offset(dy, "dy")?,
+				parse_optional_ts(dt, "dt")?.unwrap_or(0)));
+		}
+		if dt.is_some() { return Err(Error::BadFormField("invalid combination".to_string())); }
+		//Resize takes width and height, and optionally dx and dy.
+		if let (Some(width), Some(height)) = (width.as_ref(), height.as_ref()) {
+			if fields != 2 + dx.iter().count() + dy.iter().count() {
+				return Err(Error::BadFormField("invalid combination".to_string()));
+			}
+			return Ok(ScenePostForm::Resize(
+				u32::from_str(width).map_err(|_|Error::BadFormField("width".to_owned()))?,
+				u32::from_str(height).map_err(|_|Error::BadFormField("height".to_owned()))?,
+				parse_offset(dx, "dx")?, parse_offset(dy, "dy")?));
+		}
+		if width.is_some() || height.is_some() || dx.is_some() || dy.is_some() {
+			return Err(Error::BadFormField("invalid combination".to_string()));
+		}
+		//Revert takes r and optionally since and until.
+		if let Some(revert) = revert {
+			if fields != 1 + since.iter().count() + until.iter().count() {
+				return Err(Error::BadFormField("invalid combination".to_string()));
+			}
+			return Ok(ScenePostForm::Revert(revert, parse_optional_ts(since, "since")?,
+				parse_optional_ts(until, "until")?));
+		}
+		if since.is_some() || until.is_some() {
+			return Err(Error::BadFormField("invalid combination".to_string()));
+		}
 		match (grant, ungrant, ts, username, color, x, y) {
 			(Some(grant), None, None, None, None, None, None) =>
 				Ok(ScenePostForm::Grant(grant)),
@@ -316,25 +834,25 @@ impl<'r> FromForm<'r> for ScenePostForm
 	}
 }
 
+//Scenes without owner (it was deleted, or migration found none) are managed by every application with access.
+fn is_owner(conn: &Connection, scene: Scene, appid: i32) -> bool
+{
+	conn.query("SELECT owner FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().iter().next().map(|row|{
+		let owner: Option<i32> = row.get(0);
+		owner.map(|x|x == appid).unwrap_or(true)
+	}).unwrap_or(false)
+}
+
 pub fn scene_edit_post(scene: Scene, auth: AuthenticationInfo, upload: Form<ScenePostForm>) ->
 	Result<impl Responder<'static>, Error>
 {
 	let mut conn = db_connect();
 
-	auth.check_write(&mut conn, scene).map_err(|x|
+	let appid = auth.check_write(&mut conn, scene).map_err(|x|
 		if x { Error::InvalidOrigin } else { Error::SceneNotFound }
 	)?;
 
-	//Grab width and height of scene.
-	let (w, h) = if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1", &[&scene]).
-		unwrap().iter().next() {
-		let w: i32 = row.get(0);
-		let h: i32 = row.get(1);
-		(w, h)
-	} else {
-		return Err(Error::SceneNotFound);
-	};
-
+	let mut message = format!("Wrote an event\n");
 	match upload.into_inner() {
 		ScenePostForm::Grant(grant) => {
 			let appid: i32 = conn.query("SELECT appid FROM applications WHERE origin=$1 AND temporary=false",
@@ -349,18 +867,119 @@ pub fn scene_edit_post(scene: Scene, auth: AuthenticationInfo, upload: Form<Scen
 				&scene]).unwrap();
 		},
 		ScenePostForm::Event(ev) => {
+			let mut writer = EventWriter::new(scene, appid);
+			conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+			writer.lock(&conn)?;
+			writer.write(&conn, &ev)?;
+			notify_scene(&conn, scene);
+			conn.execute("COMMIT", &[]).unwrap();
+		},
+		ScenePostForm::Revert(username, start, end) => {
+			let tstart = start.unwrap_or(i64::min_value());
+			let tend = end.unwrap_or(i64::max_value());
+			conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+			let (w, h) = lock_scene(&conn, scene)?;
 			let mmap = MmapImageState::new(format!("{}/currentstate/{}", root_path(), scene.as_inner()),
 				w as usize, h as usize).unwrap();
-			mmap.write_pixel(ev.x, ev.y, ev.ts, ev.color);
-			conn.execute("INSERT INTO scene_data (sceneid,timestamp,username,color,x,y) VALUES ($1,$2,\
-				$3,$4,$5,$6) ON CONFLICT DO NOTHING", &[&scene, &ev.ts, &ev.username, &ev.color,
-				&ev.x, &ev.y]).unwrap();
+			//Cells are in stored coordinates, which are moved by origin.
+			let (ox, oy) = scene_origin(&conn, scene);
+			let mut cells = HashSet::new();
+			let mut reverted = 0;
+			for row in conn.query("DELETE FROM scene_data WHERE sceneid=$1 AND username=$2 AND timestamp>=$3 \
+				AND timestamp<=$4 RETURNING x,y", &[&scene, &username, &tstart, &tend]).unwrap().iter() {
+				let x: i32 = row.get(0);
+				let y: i32 = row.get(1);
+				cells.insert((x, y));
+				reverted += 1;
+			}
+			//Replay the remaining history of the affected cells.
+			for &(x, y) in cells.iter() {
+				if let (Some(x), Some(y)) = (x.checked_add(ox), y.checked_add(oy)) { mmap.reset_pixel(x, y); }
+			}
+			if cells.len() > 0 {
+				let (xs, ys): (Vec<i32>, Vec<i32>) = cells.iter().cloned().unzip();
+				for row in conn.query("SELECT timestamp,color,x,y FROM scene_data WHERE sceneid=$1 AND (x, y) IN \
+					(SELECT * FROM unnest($2::integer[], $3::integer[])) ORDER BY timestamp, recordid", &[&scene,
+					&xs, &ys]).unwrap().iter() {
+					let x: i32 = row.get(2);
+					let y: i32 = row.get(3);
+					if let (Some(x), Some(y)) = (x.checked_add(ox), y.checked_add(oy)) {
+						mmap.write_pixel(x, y, row.get(0), row.get(1));
+					}
+				}
+			}
+			conn.execute("COMMIT", &[]).unwrap();
+			notify_scene(&conn, scene);
+			message = format!("Reverted {} event(s)\n", reverted);
+		},
+		ScenePostForm::Rebuild => {
+			rebuild_scene(&conn, scene)?;
+			message = format!("Rebuilt the scene\n");
+		},
+		ScenePostForm::Palette(palette) => {
+			conn.execute("UPDATE scenes SET palette=$1 WHERE sceneid=$2", &[&palette, &scene]).unwrap();
+			message = match palette {
+				Some(palette) => format!("Set palette of {} color(s)\n", palette.len()),
+				None => format!("Removed palette\n")
+			};
+		},
+		ScenePostForm::Protect(r, origins, users) => {
+			if !is_owner(&conn, scene, appid) { return Err(Error::NotOwner); }
+			let mut apps = Vec::new();
+			for origin in origins.iter() {
+				let appid: i32 = conn.query("SELECT appid FROM applications WHERE origin=$1 AND temporary=false",
+					&[origin]).unwrap().iter().next().ok_or(Error::BadGrant)?.get(0);
+				apps.push(appid);
+			}
+			let regionid: i32 = conn.query("INSERT INTO scene_protected (sceneid,x,y,w,h,appids,usernames) VALUES \
+				($1,$2,$3,$4,$5,$6,$7) RETURNING regionid", &[&scene, &r.x, &r.y, &r.w, &r.h, &apps, &users]).
+				unwrap().iter().next().unwrap().get(0);
+			message = format!("Protected region {}\n", regionid);
+		},
+		ScenePostForm::Unprotect(regionid) => {
+			if !is_owner(&conn, scene, appid) { return Err(Error::NotOwner); }
+			if conn.execute("DELETE FROM scene_protected WHERE regionid=$1 AND sceneid=$2", &[&regionid, &scene]).
+				unwrap() == 0 {
+				return Err(Error::NotFound);
+			}
+			message = format!("Removed protected region {}\n", regionid);
+		},
+		ScenePostForm::Resize(width, height, dx, dy) => {
+			resize_scene(&conn, scene, width, height, dx, dy)?;
+			message = format!("Resized the scene to {}x{}\n", width, height);
+		},
+		ScenePostForm::Metadata(name, description, public) => {
+			let origin: String = conn.query("SELECT origin FROM applications WHERE appid=$1", &[&appid]).unwrap().
+				iter().next().unwrap().get(0);
+			conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+			if let Some(name) = name {
+				conn.execute("UPDATE scenes SET name=$1 WHERE sceneid=$2", &[&name, &scene]).unwrap();
+			}
+			//Empty description removes it.
+			if let Some(description) = description {
+				let description = if description == "" { None } else { Some(description) };
+				conn.execute("UPDATE scenes SET description=$1 WHERE sceneid=$2", &[&description, &scene]).
+					unwrap();
+			}
+			if let Some(public) = public {
+				conn.execute("UPDATE scenes SET public=$1 WHERE sceneid=$2", &[&public, &scene]).unwrap();
+			}
+			conn.execute("UPDATE scenes SET modified=$1, modifiedby=$2 WHERE sceneid=$3", &[&server_time(),
+				&origin, &scene]).unwrap();
+			conn.execute("COMMIT", &[]).unwrap();
+			message = format!("Updated the scene\n");
+		},
+		ScenePostForm::Merge(source, dx, dy, dt) => {
+			let (copied, clipped, rejected) = merge_scene(&conn, scene, source, appid, dx, dy, dt)?;
+			message = format!("Merged {} event(s)\n", copied);
+			if clipped > 0 { message.push_str(&format!("Clipped {} event(s) outside the scene\n", clipped)); }
+			rejected.describe(&mut message);
 		}
 	}
 	//Ok.
 	Ok(SendFileAsWithCors{
 		content_type: "text/plain",
-		content: format!("Wrote an event\n").into_bytes(),
+		content: message.into_bytes(),
 		methods: SCENE_EDIT_METHODS,
 		headers: SCENE_EDIT_HEADERS,
 	})
@@ -387,23 +1006,42 @@ pub fn scene_edit_delete(scene: Scene, auth: AuthenticationInfo) -> Result<impl
 	})
 }
 
-pub fn scene_get_png(scene: Scene) -> Result<impl Responder<'static>, Error>
+//Maximum scale factor for PNG.
+const MAX_PNG_SCALE: usize = 64;
+//Maximum number of pixels in scaled PNG.
+const MAX_PNG_PIXELS: usize = 1 << 24;
+
+pub fn scan_scaled_image_as_png<I:ImageSource>(img: &I, view: &PngView) -> Result<Vec<u8>, Error>
+{
+	let (w, h) = img.get_size();
+	if view.scale > MAX_PNG_SCALE { return Err(Error::InvalidDimensions); }
+	if view.level < 0 || view.level > 9 { return Err(Error::BadFormField("level".to_owned())); }
+	let img = ScaledView::new(img, view.x, view.y, view.w.unwrap_or(w), view.h.unwrap_or(h), view.scale).
+		ok_or(Error::InvalidDimensions)?;
+	let (w, h) = img.get_size();
+	if w.saturating_mul(h) > MAX_PNG_PIXELS { return Err(Error::InvalidDimensions); }
+	let mut out = Cursor::new(Vec::new());
+	scan_image_as_png(&mut out, &img, view.level);
+	Ok(out.into_inner())
+}
+
+pub fn scene_get_png(scene: Scene, range: GetBounds, view: PngView) -> Result<impl Responder<'static>, Error>
 {
 	let conn = db_connect();
 	//Grab width and height of scene.
-	let (w, h) = if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1", &[&scene]).
-		unwrap().iter().next() {
-		let w: i32 = row.get(0);
-		let h: i32 = row.get(1);
-		(w, h)
+	let (w, h) = scene_size(&conn, scene)?;
+	let out = if range.start.is_some() || range.end.is_some() {
+		//Historical state, replay it.
+		let tstart = range.start.unwrap_or(i64::min_value());
+		let tend = range.end.unwrap_or(i64::max_value());
+		let mut img = MemoryImageState::new(w as usize, h as usize);
+		replay_scene_range(&conn, scene, &mut img, tstart, tend);
+		scan_scaled_image_as_png(&img, &view)?
 	} else {
-		return Err(Error::SceneNotFound);
+		let mmap = MmapImageState::new(format!("{}/currentstate/{}", root_path(), scene.as_inner()),
+			w as usize, h as usize).unwrap();
+		scan_scaled_image_as_png(&mmap, &view)?
 	};
-	let mmap = MmapImageState::new(format!("{}/currentstate/{}", root_path(), scene.as_inner()),
-		w as usize, h as usize).unwrap();
-	let mut out = Cursor::new(Vec::with_capacity(scan_image_as_png_size(&mmap)));
-	scan_image_as_png(&mut out, &mmap);
-	let out = out.into_inner();
 	Ok(SendFileAsWithCors{
 		content_type: "application/png",
 		content: out,
@@ -412,12 +1050,189 @@ pub fn scene_get_png(scene: Scene) -> Result<impl Responder<'static>, Error>
 	})
 }
 
+//Maximum number of frames in timelapse.
+const MAX_TIMELAPSE_FRAMES: i64 = 10000;
+//Maximum frame rate of APNG.
+const MAX_APNG_FPS: u16 = 100;
+//Maximum frame rate of GIF. Delays are in centiseconds, and smaller than 2 are not honored.
+const MAX_GIF_FPS: u16 = 50;
+
+//Check the parameters of timelapse, and grab width and height of scene.
+fn timelapse_size(conn: &Connection, scene: Scene, view: &TimelapseView, max_fps: u16) -> Result<(usize, usize),
+	Error>
+{
+	let (w, h) = scene_size(&conn, scene)?;
+	if view.interval <= 0 { return Err(Error::BadFormField("interval".to_owned())); }
+	if view.fps == 0 || view.fps > max_fps { return Err(Error::BadFormField("fps".to_owned())); }
+	if view.level < 0 || view.level > 9 { return Err(Error::BadFormField("level".to_owned())); }
+	if w <= 0 || h <= 0 || (w as usize).saturating_mul(h as usize) > MAX_PNG_PIXELS {
+		return Err(Error::InvalidDimensions);
+	}
+	Ok((w as usize, h as usize))
+}
+
+pub fn scene_get_apng(scene: Scene, range: GetBounds, view: TimelapseView) -> Result<impl Responder<'static>,
+	Error>
+{
+	let conn = db_connect();
+	let (w, h) = timelapse_size(&conn, scene, &view, MAX_APNG_FPS)?;
+	let timelapse = Timelapse::new(&conn, scene, range.start, range.end, view.interval);
+	if timelapse.frames() > MAX_TIMELAPSE_FRAMES { return Err(Error::BadFormField("interval".to_owned())); }
+	let mut img = MemoryImageState::new(w, h);
+	let mut out = Cursor::new(Vec::new());
+	let mut apng = ApngWriter::new(&mut out, w, h, timelapse.frames() as u32, view.fps, view.level);
+	let mut first = true;
+	timelapse.replay(&mut img, |img, dirty|{
+		//Only the rectangle changed since last frame is sent, but every frame needs some content.
+		let (x, y, fw, fh) = if first { (0, 0, w, h) } else { dirty.unwrap_or((0, 0, 1, 1)) };
+		apng.write_frame(&mut out, img, x, y, fw, fh);
+		first = false;
+	});
+	apng.finish(&mut out);
+	Ok(SendFileAsWithCors{
+		content_type: "image/apng",
+		content: out.into_inner(),
+		methods: "HEAD, GET",
+		headers: ""
+	})
+}
+
+pub fn scene_get_gif(scene: Scene, range: GetBounds, view: TimelapseView) -> Result<impl Responder<'static>,
+	Error>
+{
+	let conn = db_connect();
+	let (w, h) = timelapse_size(&conn, scene, &view, MAX_GIF_FPS)?;
+	if w > 65535 || h > 65535 { return Err(Error::InvalidDimensions); }
+	let tstart = range.start.unwrap_or(i64::min_value());
+	let tend = range.end.unwrap_or(i64::max_value());
+	let colors: Vec<u32> = conn.query("SELECT DISTINCT (color & 16777215) FROM scene_view WHERE sceneid=$1 AND \
+		timestamp>=$2 AND timestamp<=$3", &[&scene, &tstart, &tend]).unwrap().iter().map(|row|{
+		let c: i32 = row.get(0);
+		c as u32
+	}).collect();
+	let timelapse = Timelapse::new(&conn, scene, range.start, range.end, view.interval);
+	if timelapse.frames() > MAX_TIMELAPSE_FRAMES { return Err(Error::BadFormField("interval".to_owned())); }
+	let mut out = Cursor::new(Vec::new());
+	let mut gif = GifWriter::new(&mut out, w, h, &colors, (100 + view.fps / 2) / view.fps).
+		ok_or(Error::TooManyColors)?;
+	let mut img = MemoryImageState::new(w, h);
+	let mut first = true;
+	timelapse.replay(&mut img, |img, dirty|{
+		let (x, y, fw, fh) = if first { (0, 0, w, h) } else { dirty.unwrap_or((0, 0, 1, 1)) };
+		gif.write_frame(&mut out, img, x, y, fw, fh);
+		first = false;
+	});
+	gif.finish(&mut out);
+	Ok(SendFileAsWithCors{
+		content_type: "image/gif",
+		content: out.into_inner(),
+		methods: "HEAD, GET",
+		headers: ""
+	})
+}
+
+pub fn scene_get_pixel(scene: Scene, x: i32, y: i32) -> Result<impl Responder<'static>, Error>
+{
+	let conn = db_connect();
+	//Grab width and height of scene.
+	let (w, h) = scene_size(&conn, scene)?;
+	if x < 0 || y < 0 || x >= w || y >= h { return Err(Error::NotFound); }
+	let mmap = MmapImageState::new(format!("{}/currentstate/{}", root_path(), scene.as_inner()), w as usize,
+		h as usize).unwrap();
+	let state = mmap.read_pixel(x, y);
+	let mut out = String::new();
+	out.push_str(r#"{"data":["#);
+	//The event shown is the last one with the timestamp and color in the current state.
+	let mut current = None;
+	for (i, row) in conn.query(&format!("SELECT d.timestamp,d.username,d.color,d.x+s.xorigin,d.y+s.yorigin FROM {} \
+		WHERE d.sceneid=$1 AND d.x=$2::integer-s.xorigin::bigint AND d.y=$3::integer-s.yorigin::bigint ORDER BY \
+		d.timestamp, d.recordid", EVENTS_WITH_ORIGIN), &[&scene, &x, &y]).unwrap().iter().enumerate() {
+		let ev = EventInfo {
+			ts: row.get(0),
+			username: row.get(1),
+			color: row.get(2),
+			x: row.get(3),
+			y: row.get(4),
+		};
+		if state == Some(((ev.color & 0xFFFFFF) as u32, ev.ts)) { current = Some(i); }
+		if i > 0 { out.push(','); }
+		format_row(&mut out, &ev);
+	}
+	out.push_str(r#"],"current":"#);
+	match current { Some(i) => write!(out, "{}", i).unwrap(), None => out.push_str("null") };
+	out.push_str("}\n");
+	Ok(SendFileAsWithCors{
+		content_type: "application/json",
+		content: out.into_bytes(),
+		methods: SCENE_METHODS,
+		headers: SCENE_HEADERS
+	})
+}
+
+pub fn scene_get_cooldown(scene: Scene, username: String) -> Result<impl Responder<'static>, Error>
+{
+	let conn = db_connect();
+	let exists: i64 = conn.query("SELECT COUNT(sceneid) FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().
+		iter().next().unwrap().get(0);
+	if exists == 0 { return Err(Error::SceneNotFound); }
+	let cooldown = SceneConfig::load(&conn, scene).cooldown;
+	let remaining = match cooldown {
+		Some(cooldown) => cooldown_remaining(&conn, scene, &username, cooldown * 1000, server_time()),
+		None => 0
+	};
+	let mut out = String::new();
+	out.push_str(r#"{"cooldown":"#);
+	match cooldown { Some(x) => write!(out, "{}", x).unwrap(), None => out.push_str("null") };
+	write!(out, r#","remaining":{}}}"#, remaining).unwrap();
+	out.push('\n');
+	Ok(SendFileAsWithCors{
+		content_type: "application/json",
+		content: out.into_bytes(),
+		methods: SCENE_METHODS,
+		headers: SCENE_HEADERS
+	})
+}
+
+pub fn scene_get_protected(scene: Scene) -> Result<impl Responder<'static>, Error>
+{
+	let conn = db_connect();
+	let exists: i64 = conn.query("SELECT COUNT(sceneid) FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().
+		iter().next().unwrap().get(0);
+	if exists == 0 { return Err(Error::SceneNotFound); }
+	let mut out = String::new();
+	out.push_str(r#"{"regions":["#);
+	for (i, p) in scene_protected(&conn, scene).iter().enumerate() {
+		if i > 0 { out.push(','); }
+		write!(out, r#"{{"id":{},"x":{},"y":{},"w":{},"h":{},"apps":["#, p.id, p.region.x, p.region.y,
+			p.region.w, p.region.h).unwrap();
+		for (j, row) in conn.query("SELECT origin FROM applications WHERE appid=ANY($1) ORDER BY origin",
+			&[&p.apps]).unwrap().iter().enumerate() {
+			let origin: String = row.get(0);
+			if j > 0 { out.push(','); }
+			write!(out, r#""{}""#, escape_json_string(&origin)).unwrap();
+		}
+		out.push_str(r#"],"users":["#);
+		for (j, user) in p.users.iter().enumerate() {
+			if j > 0 { out.push(','); }
+			write!(out, r#""{}""#, escape_json_string(user)).unwrap();
+		}
+		out.push_str("]}");
+	}
+	out.push_str("]}\n");
+	Ok(SendFileAsWithCors{
+		content_type: "application/json",
+		content: out.into_bytes(),
+		methods: SCENE_METHODS,
+		headers: SCENE_HEADERS
+	})
+}
+
 const SCENE_CONFIG_METHODS: &'static str = "HEAD, GET, PUT";
 const SCENE_CONFIG_HEADERS: &'static str = "api-origin, api-key, content-type";
 
-pub fn scene_get_lsmv(scene: Scene) -> Result<impl Responder<'static>, Error>
+pub fn scene_get_lsmv(scene: Scene, filter: EventFilter) -> Result<impl Responder<'static>, Error>
 {
-	_scene_get_lsmv(scene)
+	_scene_get_lsmv(scene, filter)
 }
 
 
@@ -434,27 +1249,14 @@ pub fn scene_config_options() -> Result<impl Responder<'static>, Error>
 pub fn scene_config_get(scene: Scene) -> Result<impl Responder<'static>, Error>
 {
 	let conn = db_connect();
-	let (_w, _h) = if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1", &[&scene]).
-		unwrap().iter().next() {
-		let w: i32 = row.get(0);
-		let h: i32 = row.get(1);
-		(w, h)
-	} else {
-		return Err(Error::SceneNotFound);
-	};
-	let mut content = Vec::new();
-	if File::open(format!("{}/sconfigs/{}", root_path(), scene.as_inner())).and_then(|mut f|f.read_to_end(
-		&mut content)).is_err() {
-		return Ok(SendFileAsWithCors{
-			content_type: "application/octet-stream",
-			content: Vec::new(),
-			methods: SCENE_CONFIG_METHODS,
-			headers: SCENE_CONFIG_HEADERS
-		})
-	}
-	return Ok(SendFileAsWithCors{
-		content_type: "application/octet-stream",
-		content: content,
+	let exists: i64 = conn.query("SELECT COUNT(sceneid) FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().
+		iter().next().unwrap().get(0);
+	if exists == 0 { return Err(Error::SceneNotFound); }
+	let mut content = SceneConfig::load(&conn, scene).to_json();
+	content.push('\n');
+	Ok(SendFileAsWithCors{
+		content_type: "application/json",
+		content: content.into_bytes(),
 		methods: SCENE_CONFIG_METHODS,
 		headers: SCENE_CONFIG_HEADERS
 	})
@@ -481,10 +1283,10 @@ pub fn scene_config_put(scene: Scene, auth: AuthenticationInfo, upload: Data) ->
 		if amt == 0 { break; }
 		fill += amt;
 	}
-	let tname = format!("{}/sconfigs/{}.tmp", root_path(), scene.as_inner());
-	let fname = format!("{}/sconfigs/{}", root_path(), scene.as_inner());
-	File::create(&tname).and_then(|mut f|f.write_all(&upbuf[..fill])).unwrap();
-	rename(&tname, &fname).unwrap();
+	//Missing palette keeps the current one, as it can also be set separately.
+	let base = SceneConfig{palette: scene_palette(&conn, scene), .. SceneConfig::default()};
+	let config = SceneConfig::parse_with(&upbuf[..fill], base).map_err(|x|Error::BadConfig(x))?;
+	config.store(&conn, scene);
 	//Ok.
 	return Ok(SendFileAsWithCors{
 		content_type: "text/plain",
@@ -526,16 +1328,20 @@ impl<'a, 'r> FromRequest<'a, 'r> for Xss
 	}
 }
 
-pub fn scene_describe(scene: Scene, xss: Xss) -> Result<impl Responder<'static>, Error>
+pub fn scene_describe(scene: Scene, xss: Xss, filter: EventFilter) -> Result<impl Responder<'static>, Error>
 {
 	let conn = db_connect();
-	let (w, h, name) = if let Some(row) = conn.query("SELECT width, height, name FROM scenes WHERE sceneid=$1",
-		&[&scene]).
+	let (w, h, name, description, public, modified) = if let Some(row) = conn.query("SELECT width, height, \
+		name, description, public, modified, modifiedby FROM scenes WHERE sceneid=$1", &[&scene]).
 		unwrap().iter().next() {
 		let w: i32 = row.get(0);
 		let h: i32 = row.get(1);
 		let name: String = row.get(2);
-		(w, h, name)
+		let description: Option<String> = row.get(3);
+		let public: bool = row.get(4);
+		let modified: Option<i64> = row.get(5);
+		let modifiedby: Option<String> = row.get(6);
+		(w, h, name, description, public, modified.map(|x|(x, modifiedby.unwrap_or(String::new()))))
 	} else {
 		return Err(Error::SceneNotFound);
 	};
@@ -552,12 +1358,29 @@ pub fn scene_describe(scene: Scene, xss: Xss) -> Result<impl Responder<'static>,
 		xml.tag_fn(tag!(body), |xml|{
 			xml.tag_fn(tag!(div attr!(class="box")), |xml|{
 				xml.text(&format!("Size: {}x{} Name: '{}'", w, h, name));
+				xml.text(if public { " Public" } else { " Private" });
 				if let Some(ref xss) = xss.0.as_ref() { xml.text(&format!(" XSS: {}", xss)); }
 			});
+			if let Some(ref description) = description {
+				xml.tag_fn(tag!(div attr!(class="box")), |xml|{
+					xml.text(description);
+				});
+			}
+			if let Some((ts, ref origin)) = modified {
+				xml.tag_fn(tag!(div attr!(class="box")), |xml|{
+					let t = at_utc(Timespec{sec: ts / 1000, nsec: 0});
+					xml.text(&format!("Changed at {} by '{}'", t.strftime("%Y-%m-%d %H:%M:%S").unwrap(),
+						origin));
+				});
+			}
 			let mut timebase = None;
 			xml.tag_fn(tag!(div attr!(class="box")), |xml|{
-				for row in conn.query("SELECT timestamp,username,color,x,y FROM scene_data WHERE \
-					sceneid=$1 ORDER BY timestamp, recordid", &[&scene]).unwrap().iter() {
+				let mut params: Vec<&ToSql> = Vec::new();
+				params.push(&scene);
+				params.extend_from_slice(&filter.params());
+				for row in conn.query(&format!("SELECT d.timestamp,d.username,d.color,d.x+s.xorigin,\
+					d.y+s.yorigin FROM {} WHERE d.sceneid=$1 AND {} ORDER BY d.timestamp, d.recordid",
+					EVENTS_WITH_ORIGIN, EventFilter::condition(2)), &params).unwrap().iter() {
 					let ts: i64 = row.get(0);
 					let username: String = row.get(1);
 					let color: i32 = row.get(2);
@@ -0,0 +1,223 @@
+use ::{db_connect, add_default_headers};
+use ::error::Error;
+use ::scene::Scene;
+use ::scene_endpoint::{EventInfo, format_row};
+use fallible_iterator::FallibleIterator;
+use postgres::Connection;
+use rocket::request::{FromRequest, Request};
+use rocket::outcome::Outcome;
+use rocket::response::{Responder, Response};
+use rocket::http::{Header, Status};
+use std::cmp::{max, min};
+use std::fmt::Write as FmtWrite;
+use std::io::Read as IoRead;
+use std::io::Error as IoError;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+//How often to send keepalive comments if nothing happens.
+const KEEPALIVE_INTERVAL: u64 = 15;
+//Maximum number of events to pull from database at once.
+pub const EVENT_BATCH: i64 = 1000;
+//Rocket fills entire chunks before sending, so the stream is padded to multiple of this.
+const SSE_CHUNK: usize = 256;
+
+//Requests waiting for events (streams and long polls) each tie up a worker thread, so only some of the workers can
+//be doing that at once.
+static WAITERS: AtomicUsize = AtomicUsize::new(0);
+static MAX_WAITERS: AtomicUsize = AtomicUsize::new(1);
+
+//Set how many requests can wait for events at once.
+pub fn set_max_waiters(count: usize)
+{
+	MAX_WAITERS.store(max(count, 1), Ordering::SeqCst);
+}
+
+//Permission to wait for events, given back when dropped.
+pub struct WaitSlot(());
+
+impl WaitSlot
+{
+	//Fails with Busy if too many requests are waiting already.
+	pub fn acquire() -> Result<WaitSlot, Error>
+	{
+		if WAITERS.fetch_add(1, Ordering::SeqCst) >= MAX_WAITERS.load(Ordering::SeqCst) {
+			WAITERS.fetch_sub(1, Ordering::SeqCst);
+			return Err(Error::Busy);
+		}
+		Ok(WaitSlot(()))
+	}
+}
+
+impl Drop for WaitSlot
+{
+	fn drop(&mut self)
+	{
+		WAITERS.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+pub fn scene_channel(scene: Scene) -> String
+{
+	format!("scene_events_{}", scene.as_inner())
+}
+
+//Signal listeners that there are new events in scene. If in transaction, this is delivered on commit.
+pub fn notify_scene(conn: &Connection, scene: Scene)
+{
+	conn.execute("SELECT pg_notify($1, '')", &[&scene_channel(scene)]).unwrap();
+}
+
+//Start receiving notifications about new events in scene.
+pub fn listen_scene(conn: &Connection, scene: Scene)
+{
+	//The channel name only contains alphanumerics and underscores, so this is safe.
+	conn.execute(&format!("LISTEN {}", scene_channel(scene)), &[]).unwrap();
+}
+
+//Stop receiving notifications about new events in scene.
+pub fn unlisten_scene(conn: &Connection, scene: Scene)
+{
+	conn.execute(&format!("UNLISTEN {}", scene_channel(scene)), &[]).unwrap();
+}
+
+//Wait for notification about new events. Returns true if some arrived, false on timeout.
+pub fn wait_scene(conn: &Connection, timeout: Duration) -> bool
+{
+	let got = conn.notifications().timeout_iter(timeout).next().ok().and_then(|x|x).is_some();
+	//Drain any other pending notifications, the caller will query everything anyway.
+	if got { while let Ok(Some(_)) = conn.notifications().iter().next() {} }
+	got
+}
+
+//Next batch of events in scene after record number last, with their record numbers. Writers keep the scene locked
+//until commit (see EventWriter::lock), so events become visible in order of record number and none get skipped.
+pub fn events_after(conn: &Connection, scene: Scene, last: i32) -> Vec<(i32, EventInfo)>
+{
+	conn.query("SELECT recordid,timestamp,username,color,x,y FROM scene_view WHERE sceneid=$1 AND recordid>$2 \
+		ORDER BY recordid LIMIT $3", &[&scene, &last, &EVENT_BATCH]).unwrap().iter().map(|row|(row.get(0),
+		EventInfo {
+			ts: row.get(1),
+			username: row.get(2),
+			color: row.get(3),
+			x: row.get(4),
+			y: row.get(5),
+		})).collect()
+}
+
+pub struct LastEventId(Option<i32>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for LastEventId
+{
+	type Error = ();
+	fn from_request(request: &'a Request<'r>) -> Outcome<LastEventId, (Status, ()), ()> {
+		let last = request.headers().get_one("last-event-id").and_then(|x|i32::from_str(x.trim()).ok());
+		Outcome::Success(LastEventId(last))
+	}
+}
+
+struct EventStream
+{
+	conn: Connection,
+	scene: Scene,
+	last: i32,
+	buffer: Vec<u8>,
+	pos: usize,
+	ended: bool,
+	_slot: WaitSlot,
+}
+
+impl EventStream
+{
+	fn refill(&mut self)
+	{
+		let mut out = String::new();
+		for (recordid, ev) in events_after(&self.conn, self.scene, self.last) {
+			write!(out, "id: {}\ndata: ", recordid).unwrap();
+			format_row(&mut out, &ev);
+			out.push_str("\n\n");
+			self.last = recordid;
+		}
+		if out.len() == 0 && !wait_scene(&self.conn, Duration::from_secs(KEEPALIVE_INTERVAL)) {
+			//Timed out. End the stream if the scene got deleted, otherwise keep the connection alive.
+			let exists: i64 = self.conn.query("SELECT COUNT(sceneid) FROM scenes WHERE sceneid=$1",
+				&[&self.scene]).unwrap().iter().next().unwrap().get(0);
+			if exists == 0 { self.ended = true; return; }
+			out.push_str(": keepalive\n\n");
+		}
+		//Pad with a comment so Rocket sends the whole thing immediately.
+		let rem = out.len() % SSE_CHUNK;
+		if rem > 0 {
+			let mut pad = SSE_CHUNK - rem;
+			if pad < 2 { pad += SSE_CHUNK; }
+			out.push(':');
+			for _ in 0..pad-2 { out.push(' '); }
+			out.push('\n');
+		}
+		self.buffer = out.into_bytes();
+		self.pos = 0;
+	}
+}
+
+impl IoRead for EventStream
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError>
+	{
+		while self.pos == self.buffer.len() {
+			if self.ended { return Ok(0); }
+			self.refill();
+		}
+		let n = min(buf.len(), self.buffer.len() - self.pos);
+		buf[..n].copy_from_slice(&self.buffer[self.pos..][..n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+pub struct SceneEventStream(EventStream);
+
+impl<'r> Responder<'r> for SceneEventStream
+{
+	fn respond_to(self, request: &Request) -> Result<Response<'r>, Status>
+	{
+		let h = request.headers();
+		let origin = h.get_one("origin").map(|x|x.to_owned());
+
+		let mut response = Response::new();
+		response.set_status(Status::new(200, "OK"));
+		response.set_header(Header::new("Content-Type", "text/event-stream"));
+		response.set_header(Header::new("Cache-Control", "no-cache"));
+		if let Some(origin) = origin { if origin.starts_with("https://") {
+			response.set_header(Header::new("Access-Control-Allow-Origin", origin));
+		}}
+		add_default_headers(&mut response);
+		response.set_chunked_body(self.0, SSE_CHUNK as u64);
+		Ok(response)
+	}
+}
+
+pub fn scene_get_events(scene: Scene, last: LastEventId) -> Result<SceneEventStream, Error>
+{
+	let slot = WaitSlot::acquire()?;
+	let conn = db_connect();
+	let exists: i64 = conn.query("SELECT COUNT(sceneid) FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().
+		iter().next().unwrap().get(0);
+	if exists == 0 { return Err(Error::SceneNotFound); }
+	//Start listening before reading the starting point, so no events are lost in between.
+	listen_scene(&conn, scene);
+	let last = match last.0 {
+		Some(x) => x,
+		None => conn.query("SELECT COALESCE(MAX(recordid),0) FROM scene_data WHERE sceneid=$1", &[&scene]).
+			unwrap().iter().next().unwrap().get(0)
+	};
+	Ok(SceneEventStream(EventStream{
+		conn: conn,
+		scene: scene,
+		last: last,
+		buffer: Vec::new(),
+		pos: 0,
+		ended: false,
+		_slot: slot,
+	}))
+}
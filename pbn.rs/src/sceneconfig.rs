@@ -0,0 +1,314 @@
+use ::root_path;
+use ::error::Error;
+use ::json::{JsonToken, JsonStream};
+use ::scene::Scene;
+use postgres::Connection;
+use std::cmp::{max, min};
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, rename};
+use std::io::Read as IoRead;
+use std::io::Write as IoWrite;
+use time::get_time;
+
+//Rectangle of cells.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Region
+{
+	pub x: i32,
+	pub y: i32,
+	pub w: i32,
+	pub h: i32,
+}
+
+impl Region
+{
+	pub fn contains(&self, x: i32, y: i32) -> bool
+	{
+		x >= self.x && y >= self.y && (x - self.x) < self.w && (y - self.y) < self.h
+	}
+	//Move by dx and dy, cutting off the part that ends up at negative coordinates. None if nothing is left.
+	pub fn translate(&self, dx: i32, dy: i32) -> Option<Region>
+	{
+		let (x, y) = (self.x as i64 + dx as i64, self.y as i64 + dy as i64);
+		let (w, h) = (self.w as i64 + min(x, 0), self.h as i64 + min(y, 0));
+		let (x, y) = (max(x, 0), max(y, 0));
+		if w <= 0 || h <= 0 || x > 0x7FFFFFFF || y > 0x7FFFFFFF { return None; }
+		Some(Region{x: x as i32, y: y as i32, w: w as i32, h: h as i32})
+	}
+}
+
+//Settings of scene. Fields that are None do not restrict anything.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct SceneConfig
+{
+	//Colors that can be written.
+	pub palette: Option<Vec<i32>>,
+	//Writes are allowed from this time (milliseconds).
+	pub open: Option<i64>,
+	//Writes are allowed until this time (milliseconds).
+	pub close: Option<i64>,
+	//Cells can only be written inside these.
+	pub regions: Option<Vec<Region>>,
+	//Seconds each user has to wait between writes.
+	pub cooldown: Option<i64>,
+}
+
+//Current time of server, in milliseconds.
+pub fn server_time() -> i64
+{
+	let now = get_time();
+	now.sec.saturating_mul(1000).saturating_add(now.nsec as i64 / 1000000)
+}
+
+//Get the palette of scene, None if any color is allowed.
+pub fn scene_palette(conn: &Connection, scene: Scene) -> Option<Vec<i32>>
+{
+	conn.query("SELECT palette FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().iter().next().and_then(|row|
+		row.get(0))
+}
+
+//Width and height of scene.
+pub fn scene_size(conn: &Connection, scene: Scene) -> Result<(i32, i32), Error>
+{
+	if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().iter().
+		next() {
+		Ok((row.get(0), row.get(1)))
+	} else {
+		Err(Error::SceneNotFound)
+	}
+}
+
+//Lock scene against writes until end of transaction, and get its width and height.
+pub fn lock_scene(conn: &Connection, scene: Scene) -> Result<(i32, i32), Error>
+{
+	if let Some(row) = conn.query("SELECT width, height FROM scenes WHERE sceneid=$1 FOR UPDATE", &[&scene]).
+		unwrap().iter().next() {
+		Ok((row.get(0), row.get(1)))
+	} else {
+		Err(Error::SceneNotFound)
+	}
+}
+
+//How much history of scene is moved by resizes. Events are stored as written, and moved by this when read (see
+//scene_view).
+pub fn scene_origin(conn: &Connection, scene: Scene) -> (i32, i32)
+{
+	conn.query("SELECT xorigin, yorigin FROM scenes WHERE sceneid=$1", &[&scene]).unwrap().iter().next().map(|row|
+		(row.get(0), row.get(1))).unwrap_or((0, 0))
+}
+
+//Append palette as JSON array, or null if scene has no palette.
+pub fn format_palette(out: &mut String, palette: &Option<Vec<i32>>)
+{
+	match palette {
+		&Some(ref palette) => write!(out, "[{}]", palette.iter().map(|c|c.to_string()).collect::<Vec<_>>().
+			join(",")).unwrap(),
+		&None => out.push_str("null")
+	};
+}
+
+//Region that only some applications and users can write to.
+pub struct ProtectedRegion
+{
+	pub id: i32,
+	pub region: Region,
+	pub apps: Vec<i32>,
+	pub users: Vec<String>,
+}
+
+impl ProtectedRegion
+{
+	pub fn allows(&self, appid: i32, username: &str) -> bool
+	{
+		self.apps.contains(&appid) || self.users.iter().any(|u|u == username)
+	}
+}
+
+//Get the protected regions of scene.
+pub fn scene_protected(conn: &Connection, scene: Scene) -> Vec<ProtectedRegion>
+{
+	conn.query("SELECT regionid, x, y, w, h, appids, usernames FROM scene_protected WHERE sceneid=$1 ORDER BY \
+		regionid", &[&scene]).unwrap().iter().map(|row|ProtectedRegion{
+		id: row.get(0),
+		region: Region{x: row.get(1), y: row.get(2), w: row.get(3), h: row.get(4)},
+		apps: row.get(5),
+		users: row.get(6),
+	}).collect()
+}
+
+fn config_file(scene: Scene) -> String
+{
+	format!("{}/sconfigs/{}", root_path(), scene.as_inner())
+}
+
+fn parse_integer<R:IoRead>(stream: &mut JsonStream<R>, name: &str, min: i64, max: i64) -> Result<i64, String>
+{
+	match stream.next(&|x|format!("Error reading '{}': {}", name, x))? {
+		JsonToken::NumericInteger(x) if x >= min && x <= max => Ok(x),
+		JsonToken::NumericInteger(x) => Err(format!("Value {} for '{}' out of range {}-{}", x, name, min, max)),
+		x => Err(format!("Expected integer for '{}', got {:?}", name, x))
+	}
+}
+
+//Returns true and consumes the token if the next one is null.
+fn parse_null<R:IoRead>(stream: &mut JsonStream<R>, name: &str) -> Result<bool, String>
+{
+	let token = stream.peek().map_err(|x|format!("Error reading '{}': {}", name, x))?;
+	if token != JsonToken::Null { return Ok(false); }
+	stream.next(&|x|format!("Error reading '{}': {}", name, x))?;
+	Ok(true)
+}
+
+fn parse_palette<R:IoRead>(stream: &mut JsonStream<R>) -> Result<Vec<i32>, String>
+{
+	let mut palette = Vec::new();
+	stream.expect_array().map_err(|x|format!("Expected 'palette' to be an array: {}", x))?;
+	stream.do_array(|stream|{
+		let c = parse_integer(stream, "palette", 0, 0xFFFFFF)? as i32;
+		if !palette.contains(&c) { palette.push(c); }
+		Ok(())
+	}, &|x|format!("Error in 'palette' array: {}", x))?;
+	Ok(palette)
+}
+
+fn parse_region<R:IoRead>(stream: &mut JsonStream<R>) -> Result<Region, String>
+{
+	let (mut x, mut y, mut w, mut h) = (None, None, None, None);
+	stream.expect_object().map_err(|x|format!("Expected region to be an object: {}", x))?;
+	stream.do_object(|stream, key|{
+		match &key[..] {
+			"x" => x = Some(parse_integer(stream, "x", 0, 0x7FFFFFFF)? as i32),
+			"y" => y = Some(parse_integer(stream, "y", 0, 0x7FFFFFFF)? as i32),
+			"w" => w = Some(parse_integer(stream, "w", 1, 0x7FFFFFFF)? as i32),
+			"h" => h = Some(parse_integer(stream, "h", 1, 0x7FFFFFFF)? as i32),
+			_ => return Err(format!("Unrecognized region key '{}'", key))
+		}
+		Ok(())
+	}, &|x|format!("Error in region object: {}", x))?;
+	match (x, y, w, h) {
+		(Some(x), Some(y), Some(w), Some(h)) => Ok(Region{x: x, y: y, w: w, h: h}),
+		_ => Err(format!("Need fields x, y, w and h for region object"))
+	}
+}
+
+impl SceneConfig
+{
+	//Parse config from JSON. Missing fields and nulls leave the setting unrestricted.
+	pub fn parse<R:IoRead>(input: R) -> Result<SceneConfig, String>
+	{
+		SceneConfig::parse_with(input, SceneConfig::default())
+	}
+	//Parse config from JSON, with missing fields taken from base. Nulls leave the setting unrestricted.
+	pub fn parse_with<R:IoRead>(input: R, base: SceneConfig) -> Result<SceneConfig, String>
+	{
+		let mut config = base;
+		let mut stream = JsonStream::new(input);
+		stream.expect_object().map_err(|x|format!("Expecting start of config object: {}", x))?;
+		stream.do_object(|stream, key|{
+			if parse_null(stream, &key)? {
+				match &key[..] {
+					"palette" => config.palette = None,
+					"open" => config.open = None,
+					"close" => config.close = None,
+					"cooldown" => config.cooldown = None,
+					"regions" => config.regions = None,
+					_ => return Err(format!("Unrecognized config key '{}'", key))
+				}
+				return Ok(());
+			}
+			match &key[..] {
+				"palette" => config.palette = Some(parse_palette(stream)?),
+				"open" => config.open = Some(parse_integer(stream, "open", i64::min_value(),
+					i64::max_value())?),
+				"close" => config.close = Some(parse_integer(stream, "close", i64::min_value(),
+					i64::max_value())?),
+				"cooldown" => config.cooldown = Some(parse_integer(stream, "cooldown", 1, 0x7FFFFFFF)?),
+				"regions" => {
+					let mut regions = Vec::new();
+					stream.expect_array().map_err(|x|format!("Expected 'regions' to be an array: {}",
+						x))?;
+					stream.do_array(|stream|{
+						regions.push(parse_region(stream)?);
+						Ok(())
+					}, &|x|format!("Error in 'regions' array: {}", x))?;
+					config.regions = Some(regions);
+				},
+				_ => return Err(format!("Unrecognized config key '{}'", key))
+			}
+			Ok(())
+		}, &|x|format!("Error in config object: {}", x))?;
+		stream.expect_end_of_json().map_err(|x|format!("Expected end of JSON: {}", x))?;
+		if let (Some(open), Some(close)) = (config.open, config.close) {
+			if open >= close { return Err(format!("'open' must be before 'close'")); }
+		}
+		Ok(config)
+	}
+	pub fn to_json(&self) -> String
+	{
+		let mut out = String::new();
+		out.push_str(r#"{"palette":"#);
+		format_palette(&mut out, &self.palette);
+		out.push_str(r#","open":"#);
+		match self.open { Some(x) => write!(out, "{}", x).unwrap(), None => out.push_str("null") };
+		out.push_str(r#","close":"#);
+		match self.close { Some(x) => write!(out, "{}", x).unwrap(), None => out.push_str("null") };
+		out.push_str(r#","regions":"#);
+		match self.regions {
+			Some(ref regions) => {
+				out.push('[');
+				for (i, r) in regions.iter().enumerate() {
+					if i > 0 { out.push(','); }
+					write!(out, r#"{{"x":{},"y":{},"w":{},"h":{}}}"#, r.x, r.y, r.w, r.h).unwrap();
+				}
+				out.push(']');
+			},
+			None => out.push_str("null")
+		};
+		out.push_str(r#","cooldown":"#);
+		match self.cooldown { Some(x) => write!(out, "{}", x).unwrap(), None => out.push_str("null") };
+		out.push('}');
+		out
+	}
+	//Read the config file of scene. None if there is no file.
+	fn read_file(scene: Scene) -> Option<Result<SceneConfig, String>>
+	{
+		let mut content = Vec::new();
+		match File::open(config_file(scene)).and_then(|mut f|f.read_to_end(&mut content)) {
+			Ok(_) => Some(SceneConfig::parse(&content[..])),
+			Err(_) => None
+		}
+	}
+	//The palette is stored with the scene, the rest in the config file. Files that are not valid config (from
+	//before configs had a format) are ignored.
+	pub fn load(conn: &Connection, scene: Scene) -> SceneConfig
+	{
+		let mut config = match SceneConfig::read_file(scene) {
+			Some(Ok(x)) => x,
+			Some(Err(x)) => {
+				eprintln!("Ignoring invalid config of scene {}: {}", scene.as_inner(), x);
+				SceneConfig::default()
+			},
+			None => SceneConfig::default()
+		};
+		config.palette = scene_palette(conn, scene);
+		config
+	}
+	pub fn store(&self, conn: &Connection, scene: Scene)
+	{
+		conn.execute("UPDATE scenes SET palette=$1 WHERE sceneid=$2", &[&self.palette, &scene]).unwrap();
+		self.store_file(scene);
+	}
+	//Write the config file only. Invalid old file is kept aside as <sceneid>.legacy instead of being lost.
+	pub fn store_file(&self, scene: Scene)
+	{
+		if let Some(Err(_)) = SceneConfig::read_file(scene) {
+			let lname = format!("{}.legacy", config_file(scene));
+			eprintln!("Moving invalid config of scene {} to '{}'", scene.as_inner(), lname);
+			rename(&config_file(scene), &lname).unwrap();
+		}
+		let file = SceneConfig{palette: None, .. self.clone()};
+		let tname = format!("{}.tmp", config_file(scene));
+		File::create(&tname).and_then(|mut f|f.write_all(file.to_json().as_bytes())).unwrap();
+		rename(&tname, &config_file(scene)).unwrap();
+	}
+}
@@ -3,7 +3,9 @@ use ::authentication::AuthenticationInfo;
 use ::cors::SendFileAsWithCors;
 use ::error::Error;
 use ::json::escape_json_string;
+use ::replay::rebuild_scene;
 use ::scene::Scene;
+use ::sceneconfig::SceneConfig;
 use rocket::request::Form;
 use rocket::response::Responder;
 use std::str::from_utf8;
@@ -29,9 +31,9 @@ pub fn scenes_get(auth: AuthenticationInfo) -> impl Responder<'static>
 	let mut conn = db_connect();
 	let appid = auth.get_origin(&mut conn, false).map_err(|_|Error::InvalidOrigin)?;
 	let mut retval: Vec<(String, String)> = Vec::new();
-	for row in conn.query("SELECT application_scene.sceneid AS sceneid, scenes.name AS name FROM \
-		application_scene, scenes WHERE appid=$1 AND scenes.sceneid=application_scene.sceneid", &[&appid]).
-		unwrap().iter() {
+	//Public scenes are listed for everyone.
+	for row in conn.query("SELECT sceneid, name FROM scenes WHERE public OR sceneid IN (SELECT sceneid FROM \
+		application_scene WHERE appid=$1)", &[&appid]).unwrap().iter() {
 		let x: Scene = row.get(0);
 		let x = from_utf8(&x.scramble()).unwrap().to_owned();
 		let y: String = row.get(1);
@@ -55,8 +57,8 @@ pub fn scenes_get(auth: AuthenticationInfo) -> impl Responder<'static>
 	})
 }
 
-const MAXI32: u32 = 0x7FFFFFFF;
-const MAXPIXELS: u32 = 1 << 21;
+pub const MAXI32: u32 = 0x7FFFFFFF;
+pub const MAXPIXELS: u32 = 1 << 21;
 
 #[derive(FromForm)]
 pub struct SceneInfo
@@ -79,8 +81,8 @@ pub fn scenes_post(auth: AuthenticationInfo, upload: Form<SceneInfo>) -> impl Re
 	} else {
 		return Err(Error::InvalidDimensions);
 	};
-	let scene: Scene = conn.query("INSERT INTO scenes (name,width,height) VALUES ($1,$2,$3) RETURNING sceneid",
-		&[&name, &w, &h]).unwrap().iter().next().unwrap().get(0);
+	let scene: Scene = conn.query("INSERT INTO scenes (name,width,height,owner) VALUES ($1,$2,$3,$4) RETURNING \
+		sceneid", &[&name, &w, &h, &appid]).unwrap().iter().next().unwrap().get(0);
 	conn.execute("INSERT INTO application_scene (appid,sceneid) VALUES ($1,$2)", &[&appid, &scene]).unwrap();
 	let out = format!(r#"{{"scene":{}}}"#, from_utf8(&scene.scramble()).unwrap());
 	//Return with headers.
@@ -91,3 +93,75 @@ pub fn scenes_post(auth: AuthenticationInfo, upload: Form<SceneInfo>) -> impl Re
 		headers: SCENES_HEADERS,
 	})
 }
+
+const SCENE_FORK_METHODS: &'static str = "POST";
+
+pub fn scene_fork_options() -> Result<impl Responder<'static>, Error>
+{
+	Ok(SendFileAsWithCors{
+		content_type: "text/plain",
+		content: Vec::new(),
+		methods: SCENE_FORK_METHODS,
+		headers: SCENES_HEADERS,
+	})
+}
+
+#[derive(FromForm)]
+pub struct ForkInfo
+{
+	name: Option<String>,
+	at: Option<i64>,
+	history: Option<bool>,
+}
+
+pub fn scene_fork(scene: Scene, auth: AuthenticationInfo, upload: Form<ForkInfo>) ->
+	Result<impl Responder<'static>, Error>
+{
+	let mut conn = db_connect();
+	let appid = auth.get_origin(&mut conn, true).map_err(|_|Error::InvalidOrigin)?;
+
+	let upload = upload.into_inner();
+	let (w, h, name, description) = if let Some(row) = conn.query("SELECT width, height, name, description FROM \
+		scenes WHERE sceneid=$1", &[&scene]).unwrap().iter().next() {
+		let w: i32 = row.get(0);
+		let h: i32 = row.get(1);
+		let name: String = row.get(2);
+		let description: Option<String> = row.get(3);
+		(w, h, name, description)
+	} else {
+		return Err(Error::SceneNotFound);
+	};
+	let name = upload.name.unwrap_or(format!("{} (fork)", name));
+	let tend = upload.at.unwrap_or(i64::max_value());
+	let config = SceneConfig::load(&conn, scene);
+	conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+	let fork: Scene = conn.query("INSERT INTO scenes (name,width,height,description,owner,palette) VALUES ($1,$2,$3,\
+		$4,$5,$6) RETURNING sceneid", &[&name, &w, &h, &description, &appid, &config.palette]).unwrap().iter().next().
+		unwrap().get(0);
+	conn.execute("INSERT INTO application_scene (appid,sceneid) VALUES ($1,$2)", &[&appid, &fork]).unwrap();
+	conn.execute("INSERT INTO scene_protected (sceneid,x,y,w,h,appids,usernames) SELECT $1,x,y,w,h,appids,usernames \
+		FROM scene_protected WHERE sceneid=$2 ORDER BY regionid", &[&fork, &scene]).unwrap();
+	if upload.history.unwrap_or(true) {
+		conn.execute("INSERT INTO scene_data (sceneid,timestamp,username,color,x,y) SELECT $1,timestamp,username,\
+			color,x,y FROM scene_view WHERE sceneid=$2 AND timestamp<=$3 ORDER BY timestamp, recordid",
+			&[&fork, &scene, &tend]).unwrap();
+	} else {
+		//Just the event shown in each cell.
+		conn.execute("INSERT INTO scene_data (sceneid,timestamp,username,color,x,y) SELECT $1,timestamp,username,\
+			color,x,y FROM (SELECT DISTINCT ON (x, y) recordid,timestamp,username,color,x,y FROM scene_view WHERE \
+			sceneid=$2 AND timestamp<=$3 AND x>=0 AND y>=0 AND x<$4 AND y<$5 ORDER BY x, y, timestamp DESC, \
+			recordid DESC) AS shown ORDER BY timestamp, recordid", &[&fork, &scene, &tend, &w, &h]).unwrap();
+	}
+	conn.execute("COMMIT", &[]).unwrap();
+	//The file is not part of the transaction, so only write it once the scene exists.
+	config.store_file(fork);
+	rebuild_scene(&conn, fork)?;
+	let out = format!(r#"{{"scene":{}}}"#, from_utf8(&fork.scramble()).unwrap());
+	//Return with headers.
+	Ok(SendFileAsWithCors{
+		content_type: "application/json",
+		content: out.into_bytes(),
+		methods: SCENE_FORK_METHODS,
+		headers: SCENES_HEADERS,
+	})
+}
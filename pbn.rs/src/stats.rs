@@ -0,0 +1,82 @@
+use ::{db_connect, root_path};
+use ::cors::SendFileAsWithCors;
+use ::error::Error;
+use ::json::escape_json_string;
+use ::mmapstate::MmapImageState;
+use ::scene::Scene;
+use ::sceneconfig::scene_size;
+use rocket::response::Responder;
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+
+//Number of most used colors to list for each user.
+const TOP_COLORS: usize = 5;
+
+struct UserStats
+{
+	username: String,
+	count: i64,
+	first: i64,
+	last: i64,
+	cells: u64,
+	colors: Vec<(i32, i64)>,
+}
+
+pub fn scene_get_user_stats(scene: Scene) -> Result<impl Responder<'static>, Error>
+{
+	let conn = db_connect();
+	//Grab width and height of scene.
+	let (w, h) = scene_size(&conn, scene)?;
+	let mut users = Vec::new();
+	let mut index = HashMap::new();
+	for row in conn.query("SELECT username, COUNT(*), MIN(timestamp), MAX(timestamp) FROM scene_view WHERE \
+		sceneid=$1 GROUP BY username ORDER BY COUNT(*) DESC, username", &[&scene]).unwrap().iter() {
+		let username: String = row.get(0);
+		index.insert(username.clone(), users.len());
+		users.push(UserStats{
+			username: username,
+			count: row.get(1),
+			first: row.get(2),
+			last: row.get(3),
+			cells: 0,
+			colors: Vec::new(),
+		});
+	}
+	for row in conn.query("SELECT username, color, COUNT(*) FROM scene_view WHERE sceneid=$1 GROUP BY username, \
+		color ORDER BY COUNT(*) DESC, color", &[&scene]).unwrap().iter() {
+		let username: String = row.get(0);
+		if let Some(&i) = index.get(&username) {
+			if users[i].colors.len() < TOP_COLORS { users[i].colors.push((row.get(1), row.get(2))); }
+		}
+	}
+	//A cell is owned by the author of the event shown in it, which is the last event with its timestamp and color.
+	let mmap = MmapImageState::new(format!("{}/currentstate/{}", root_path(), scene.as_inner()), w as usize,
+		h as usize).unwrap();
+	for row in conn.query("SELECT DISTINCT ON (x, y) x, y, timestamp, color, username FROM scene_view WHERE \
+		sceneid=$1 AND x>=0 AND y>=0 AND x<$2 AND y<$3 ORDER BY x, y, timestamp DESC, recordid DESC",
+		&[&scene, &w, &h]).unwrap().iter() {
+		let color: i32 = row.get(3);
+		let username: String = row.get(4);
+		if mmap.read_pixel(row.get(0), row.get(1)) != Some(((color & 0xFFFFFF) as u32, row.get(2))) { continue; }
+		if let Some(&i) = index.get(&username) { users[i].cells += 1; }
+	}
+	let mut out = String::new();
+	out.push_str(r#"{"users":["#);
+	for (i, user) in users.iter().enumerate() {
+		if i > 0 { out.push(','); }
+		write!(out, r#"{{"u":"{}","count":{},"first":{},"last":{},"cells":{},"colors":["#,
+			escape_json_string(&user.username), user.count, user.first, user.last, user.cells).unwrap();
+		for (j, &(color, count)) in user.colors.iter().enumerate() {
+			if j > 0 { out.push(','); }
+			write!(out, r#"{{"c":{},"count":{}}}"#, color, count).unwrap();
+		}
+		out.push_str("]}");
+	}
+	out.push_str("]}\n");
+	Ok(SendFileAsWithCors{
+		content_type: "application/json",
+		content: out.into_bytes(),
+		methods: "HEAD, GET",
+		headers: ""
+	})
+}
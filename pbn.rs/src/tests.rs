@@ -231,3 +231,522 @@ fn parse_event_streams_invalid()
 	let mut stream = &stream[..]; assert!(parse_event_stream(&mut stream).is_err());
 }
 */
+
+use super::imagestate::{ImageSource, ImageState, MemoryImageState};
+use super::png::{crc32, paeth, scan_image_as_png, ApngWriter, ScaledView};
+use super::gif::GifWriter;
+use super::sceneconfig::{Region, SceneConfig};
+use super::scene_endpoint::{EventFilter, decode_cursor, encode_cursor};
+use libc::c_ulong;
+use std::ptr::null;
+
+#[link(name = "z")]
+extern
+{
+	fn uncompress(dest: *mut u8, dest_len: *mut c_ulong, source: *const u8, source_len: c_ulong) -> i32;
+}
+
+fn be32(x: &[u8]) -> usize
+{
+	(x[0] as usize) << 24 | (x[1] as usize) << 16 | (x[2] as usize) << 8 | (x[3] as usize)
+}
+
+//Decode RGBA or indexed PNG into RGBA, checking the chunk CRCs. Returns width, height, color type, number of
+//IDAT chunks and the pixels.
+#[allow(unsafe_code)]
+fn decode_png(png: &[u8]) -> (usize, usize, u8, usize, Vec<u8>)
+{
+	assert_eq!(&png[..8], &[137,80,78,71,13,10,26,10]);
+	let mut pos = 8;
+	let mut w = 0;
+	let mut h = 0;
+	let mut depth = 0;
+	let mut ctype = 0;
+	let mut palette = Vec::new();
+	let mut idats = 0;
+	let mut zdata = Vec::new();
+	loop {
+		let len = be32(&png[pos..]);
+		let chunk = &png[pos+4..pos+8+len];
+		let crc = unsafe{crc32(crc32(0, null(), 0), chunk.as_ptr(), chunk.len() as u32)};
+		assert_eq!(be32(&png[pos+8+len..]), crc as usize);
+		pos += 12 + len;
+		let data = &chunk[4..];
+		match &chunk[..4] {
+			b"IHDR" => {
+				w = be32(&data[0..]);
+				h = be32(&data[4..]);
+				depth = data[8];
+				ctype = data[9];
+				assert!((ctype == 6 && depth == 8) || (ctype == 3 && [1, 2, 4, 8].contains(&depth)));
+				assert_eq!(&data[10..], &[0, 0, 0]);
+			},
+			b"PLTE" => {
+				assert_eq!(ctype, 3);
+				for c in data.chunks(3) { palette.push([c[0], c[1], c[2], 255]); }
+			},
+			b"tRNS" => {
+				for (i, &a) in data.iter().enumerate() { palette[i][3] = a; }
+			},
+			b"IDAT" => { zdata.extend_from_slice(data); idats += 1; },
+			b"IEND" => break,
+			x => panic!("Unexpected chunk {:?}", x)
+		}
+	}
+	assert_eq!(pos, png.len());
+	let (stride, bpp) = if ctype == 3 { ((w * depth as usize + 7) / 8, 1) } else { (4 * w, 4) };
+	let mut raw = vec![0; (stride + 1) * h];
+	let mut rawlen = raw.len() as c_ulong;
+	assert_eq!(unsafe{uncompress(raw.as_mut_ptr(), &mut rawlen, zdata.as_ptr(), zdata.len() as c_ulong)}, 0);
+	assert_eq!(rawlen as usize, raw.len());
+	let mut unfiltered = vec![0; stride * h];
+	for y in 0..h {
+		let ftype = raw[y * (stride + 1)];
+		for i in 0..stride {
+			let a = if i >= bpp { unfiltered[y * stride + i - bpp] } else { 0 };
+			let b = if y > 0 { unfiltered[(y - 1) * stride + i] } else { 0 };
+			let c = if i >= bpp && y > 0 { unfiltered[(y - 1) * stride + i - bpp] } else { 0 };
+			let pred = match ftype {
+				0 => 0,
+				1 => a,
+				2 => b,
+				3 => ((a as u16 + b as u16) / 2) as u8,
+				4 => paeth(a, b, c),
+				x => panic!("Bad filter type {}", x)
+			};
+			unfiltered[y * stride + i] = raw[y * (stride + 1) + 1 + i].wrapping_add(pred);
+		}
+	}
+	if ctype == 6 { return (w, h, ctype, idats, unfiltered); }
+	let mut out = Vec::with_capacity(4 * w * h);
+	let depth = depth as usize;
+	for y in 0..h {
+		for x in 0..w {
+			let bit = x * depth;
+			let idx = (unfiltered[y * stride + bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+			out.extend_from_slice(&palette[idx as usize]);
+		}
+	}
+	(w, h, ctype, idats, out)
+}
+
+fn image_pixels<I:ImageSource>(img: &I) -> Vec<u8>
+{
+	let (w, h) = img.get_size();
+	let mut out = vec![0; 4 * w * h];
+	for y in 0..h { img.read_row(y, &mut out[4 * w * y..][..4 * w]); }
+	out
+}
+
+//Unwritten pixels decode as transparent black.
+fn check_png_roundtrip<I:ImageSource>(img: &I, level: i32) -> (usize, u8, usize)
+{
+	let mut png = Vec::new();
+	scan_image_as_png(&mut png, img, level);
+	let (w, h, ctype, idats, pixels) = decode_png(&png);
+	assert_eq!((w, h), img.get_size());
+	assert!(pixels == image_pixels(img));
+	(png.len(), ctype, idats)
+}
+
+#[test]
+fn png_roundtrip_pattern()
+{
+	let mut img = MemoryImageState::new(37, 23);
+	for y in 0..23 {
+		for x in 0..37 {
+			//Leave some cells unwritten.
+			if (x + y) % 7 == 0 { continue; }
+			img.write_pixel(x, y, 1, (x * 0x070503 + y * 0x110D0B) & 0xFFFFFF);
+		}
+	}
+	for level in 0..10 { check_png_roundtrip(&img, level); }
+}
+
+#[test]
+fn png_roundtrip_noise()
+{
+	//Incompressible data, so the output spans multiple IDAT chunks.
+	let mut img = MemoryImageState::new(300, 300);
+	let mut seed: u32 = 12345;
+	for y in 0..300 {
+		for x in 0..300 {
+			seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+			img.write_pixel(x, y, 1, (seed >> 8) as i32);
+		}
+	}
+	let (_, ctype, idats) = check_png_roundtrip(&img, 6);
+	assert_eq!(ctype, 6);
+	assert!(idats > 1);
+	check_png_roundtrip(&img, 0);
+}
+
+#[test]
+fn png_compresses_empty()
+{
+	let mut img = MemoryImageState::new(512, 512);
+	img.write_pixel(100, 100, 1, 0xFF0000);
+	let (size, _, _) = check_png_roundtrip(&img, 9);
+	assert!(size < 4096);
+	let (size, _, _) = check_png_roundtrip(&img, 0);
+	assert!(size > 512 * 512 / 8);
+}
+
+#[test]
+fn png_palette_depths()
+{
+	//1, 2, 4 and 8 bits per pixel, with and without transparent cells. Odd width to test partial bytes.
+	for &(colors, fill) in [(2, false), (2, true), (3, true), (16, true), (17, false), (256, true), (255, false)].
+		iter() {
+		let mut img = MemoryImageState::new(29, 19);
+		let mut n = 0;
+		for y in 0..19 {
+			for x in 0..29 {
+				if !fill && x == 5 && y == 7 { continue; }
+				img.write_pixel(x, y, 1, ((n % colors) * 0x010305) & 0xFFFFFF);
+				n += 1;
+			}
+		}
+		for level in 0..10 {
+			let (_, ctype, _) = check_png_roundtrip(&img, level);
+			assert_eq!(ctype, 3);
+		}
+	}
+}
+
+#[test]
+fn png_too_many_colors()
+{
+	let mut img = MemoryImageState::new(17, 17);
+	for y in 0..17 { for x in 0..17 { img.write_pixel(x, y, 1, x * 17 + y); } }
+	let (_, ctype, _) = check_png_roundtrip(&img, 6);
+	assert_eq!(ctype, 6);
+}
+
+#[test]
+#[allow(unsafe_code)]
+fn apng_frame_sequence()
+{
+	let mut img = MemoryImageState::new(8, 6);
+	let mut png = Vec::new();
+	let mut apng = ApngWriter::new(&mut png, 8, 6, 3, 25, 6);
+	apng.write_frame(&mut png, &img, 0, 0, 8, 6);
+	img.write_pixel(2, 3, 1, 0x00FF00);
+	apng.write_frame(&mut png, &img, 2, 3, 1, 1);
+	img.write_pixel(7, 5, 2, 0x0000FF);
+	apng.write_frame(&mut png, &img, 6, 4, 2, 2);
+	apng.finish(&mut png);
+	//Collect chunks, checking the CRCs and sequence numbers.
+	let mut pos = 8;
+	let mut chunks = Vec::new();
+	let mut seq = 0;
+	while pos < png.len() {
+		let len = be32(&png[pos..]);
+		let chunk = &png[pos+4..pos+8+len];
+		let crc = unsafe{crc32(crc32(0, null(), 0), chunk.as_ptr(), chunk.len() as u32)};
+		assert_eq!(be32(&png[pos+8+len..]), crc as usize);
+		pos += 12 + len;
+		let data = &chunk[4..];
+		match &chunk[..4] {
+			b"acTL" => assert_eq!((be32(&data[0..]), be32(&data[4..])), (3, 0)),
+			b"fcTL" | b"fdAT" => { assert_eq!(be32(data), seq); seq += 1; },
+			_ => ()
+		}
+		if &chunk[..4] == b"fcTL" {
+			//Width, height, x, y and delay.
+			let rect = (be32(&data[4..]), be32(&data[8..]), be32(&data[12..]), be32(&data[16..]));
+			assert!([(8, 6, 0, 0), (1, 1, 2, 3), (2, 2, 6, 4)].contains(&rect));
+			assert_eq!(&data[20..], &[0, 1, 0, 25, 0, 0]);
+		}
+		if &chunk[..4] == b"fdAT" && seq == 3 {
+			let mut raw = [0; 16];
+			let mut rawlen = raw.len() as c_ulong;
+			assert_eq!(unsafe{uncompress(raw.as_mut_ptr(), &mut rawlen, data[4..].as_ptr(),
+				(data.len() - 4) as c_ulong)}, 0);
+			assert_eq!(rawlen, 5);
+			assert_eq!(&raw[1..5], &[0, 255, 0, 255]);
+		}
+		chunks.push(String::from_utf8(chunk[..4].to_vec()).unwrap());
+	}
+	assert_eq!(pos, png.len());
+	assert_eq!(chunks, ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND"]);
+}
+
+//Decode frames of GIF written by GifWriter. Returns the global palette and (x, y, w, h, indices) of each frame.
+fn decode_gif(gif: &[u8]) -> (Vec<u32>, Vec<(usize, usize, usize, usize, Vec<u8>)>)
+{
+	let le16 = |x: &[u8]|x[0] as usize | (x[1] as usize) << 8;
+	assert_eq!(&gif[..6], b"GIF89a");
+	assert_eq!(gif[10] & 0x80, 0x80);
+	let colors = 2 << (gif[10] & 7);
+	let palette = gif[13..13+3*colors].chunks(3).map(|c|(c[0] as u32) << 16 | (c[1] as u32) << 8 | c[2] as u32).
+		collect();
+	let mut pos = 13 + 3 * colors;
+	let mut frames = Vec::new();
+	loop {
+		match gif[pos] {
+			0x21 => {
+				pos += 2;
+				while gif[pos] > 0 { pos += gif[pos] as usize + 1; }
+				pos += 1;
+			},
+			0x2C => {
+				let (x, y, w, h) = (le16(&gif[pos+1..]), le16(&gif[pos+3..]), le16(&gif[pos+5..]),
+					le16(&gif[pos+7..]));
+				assert_eq!(gif[pos+9], 0);
+				let min_size = gif[pos+10];
+				pos += 11;
+				let mut data = Vec::new();
+				while gif[pos] > 0 {
+					data.extend_from_slice(&gif[pos+1..][..gif[pos] as usize]);
+					pos += gif[pos] as usize + 1;
+				}
+				pos += 1;
+				//LZW decode.
+				let clear = 1usize << min_size;
+				let mut dict: Vec<Vec<u8>> = Vec::new();
+				let mut size = min_size + 1;
+				let mut bitpos = 0;
+				let mut prev: Option<usize> = None;
+				let mut out = Vec::new();
+				loop {
+					let mut code = 0;
+					for i in 0..size as usize {
+						let bit = bitpos + i;
+						code |= ((data[bit / 8] >> (bit % 8)) as usize & 1) << i;
+					}
+					bitpos += size as usize;
+					if code == clear {
+						dict = (0..clear).map(|i|vec![i as u8]).collect();
+						dict.push(Vec::new());
+						dict.push(Vec::new());
+						size = min_size + 1;
+						prev = None;
+						continue;
+					}
+					if code == clear + 1 { break; }
+					let entry = if code < dict.len() {
+						dict[code].clone()
+					} else {
+						let mut e = dict[prev.unwrap()].clone();
+						let first = e[0];
+						e.push(first);
+						e
+					};
+					if let Some(p) = prev {
+						let mut e = dict[p].clone();
+						e.push(entry[0]);
+						dict.push(e);
+						if dict.len() == 1 << size && size < 12 { size += 1; }
+					}
+					out.extend_from_slice(&entry);
+					prev = Some(code);
+				}
+				assert_eq!(out.len(), w * h);
+				frames.push((x, y, w, h, out));
+			},
+			0x3B => break,
+			x => panic!("Unexpected block {}", x)
+		}
+	}
+	assert_eq!(pos + 1, gif.len());
+	(palette, frames)
+}
+
+#[test]
+fn gif_lzw_roundtrip()
+{
+	//Enough noise to fill the code table several times.
+	let colors: Vec<u32> = (0..200).map(|i|i * 0x010203).collect();
+	let mut img = MemoryImageState::new(150, 120);
+	let mut seed: u32 = 12345;
+	for y in 0..120 {
+		for x in 0..150 {
+			seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+			if (seed >> 4) % 10 > 0 { img.write_pixel(x, y, 1, colors[(seed >> 8) as usize % 200] as i32); }
+		}
+	}
+	let mut gif = Vec::new();
+	let mut writer = GifWriter::new(&mut gif, 150, 120, &colors, 10).unwrap();
+	writer.write_frame(&mut gif, &img, 0, 0, 150, 120);
+	writer.write_frame(&mut gif, &img, 3, 4, 5, 6);
+	writer.finish(&mut gif);
+	let (palette, frames) = decode_gif(&gif);
+	assert_eq!(frames.len(), 2);
+	let pixels = image_pixels(&img);
+	for &(fx, fy, fw, fh, ref indices) in frames.iter() {
+		for y in 0..fh {
+			for x in 0..fw {
+				let px = &pixels[4 * (150 * (fy + y) + fx + x)..][..4];
+				let i = indices[y * fw + x] as usize;
+				if px[3] == 0 {
+					assert_eq!(i, 0);
+				} else {
+					assert!(i > 0);
+					assert_eq!(palette[i], (px[0] as u32) << 16 | (px[1] as u32) << 8 | px[2] as u32);
+				}
+			}
+		}
+	}
+}
+
+#[test]
+fn gif_few_colors()
+{
+	//One color plus transparency uses the smallest code size.
+	let mut img = MemoryImageState::new(5, 3);
+	img.write_pixel(1, 1, 1, 0xFF0000);
+	let mut gif = Vec::new();
+	let mut writer = GifWriter::new(&mut gif, 5, 3, &[0xFF0000], 10).unwrap();
+	writer.write_frame(&mut gif, &img, 0, 0, 5, 3);
+	writer.finish(&mut gif);
+	let (palette, frames) = decode_gif(&gif);
+	assert_eq!(palette, [0, 0xFF0000]);
+	assert_eq!(frames[0].4, [0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+	assert!(GifWriter::new(&mut Vec::<u8>::new(), 5, 3, &(0..256).collect::<Vec<u32>>(), 10).is_none());
+}
+
+#[test]
+fn scene_config_roundtrip()
+{
+	let config = SceneConfig::parse(&br#"{"palette":[0,255,16777215,255],"open":-5,"close":1000,
+		"regions":[{"x":1,"y":2,"w":3,"h":4}],"cooldown":30}"#[..]).unwrap();
+	assert_eq!(config.palette, Some(vec![0, 255, 16777215]));
+	assert_eq!((config.open, config.close), (Some(-5), Some(1000)));
+	assert_eq!(config.regions, Some(vec![Region{x: 1, y: 2, w: 3, h: 4}]));
+	assert_eq!(config.cooldown, Some(30));
+	assert_eq!(SceneConfig::parse(config.to_json().as_bytes()).unwrap(), config);
+	let empty = SceneConfig::parse(&br#"{"palette":null,"regions":null}"#[..]).unwrap();
+	assert_eq!(empty, SceneConfig::default());
+	assert_eq!(SceneConfig::parse(empty.to_json().as_bytes()).unwrap(), empty);
+	let r = Region{x: 1, y: 2, w: 3, h: 4};
+	assert!(r.contains(1, 2) && r.contains(3, 5));
+	assert!(!r.contains(0, 2) && !r.contains(4, 2) && !r.contains(1, 6));
+}
+
+#[test]
+fn scene_config_parse_with()
+{
+	let base = SceneConfig{palette: Some(vec![0, 255]), cooldown: Some(5), .. SceneConfig::default()};
+	let config = SceneConfig::parse_with(&br#"{"open":1}"#[..], base.clone()).unwrap();
+	assert_eq!(config, SceneConfig{open: Some(1), .. base.clone()});
+	let config = SceneConfig::parse_with(&br#"{"palette":null,"cooldown":null}"#[..], base).unwrap();
+	assert_eq!(config, SceneConfig::default());
+}
+
+#[test]
+fn region_translate()
+{
+	let r = Region{x: 1, y: 2, w: 3, h: 4};
+	assert_eq!(r.translate(5, 6), Some(Region{x: 6, y: 8, w: 3, h: 4}));
+	assert_eq!(r.translate(-2, -3), Some(Region{x: 0, y: 0, w: 2, h: 3}));
+	assert_eq!(r.translate(-4, 0), None);
+	assert_eq!(r.translate(0, -6), None);
+	assert_eq!(Region{x: 0x7FFFFFFF, y: 0, w: 1, h: 1}.translate(1, 0), None);
+}
+
+#[test]
+fn scene_config_invalid()
+{
+	for &(config, error) in [
+		(&b"[]"[..], "Expecting start of config object"),
+		(&b"{\"foo\":1}"[..], "Unrecognized config key 'foo'"),
+		(&b"{\"foo\":null}"[..], "Unrecognized config key 'foo'"),
+		(&b"{\"palette\":[16777216]}"[..], "out of range"),
+		(&b"{\"palette\":1}"[..], "Expected 'palette' to be an array"),
+		(&b"{\"open\":\"x\"}"[..], "Expected integer for 'open'"),
+		(&b"{\"open\":5,\"close\":5}"[..], "'open' must be before 'close'"),
+		(&b"{\"regions\":[{\"x\":1,\"y\":2,\"w\":0,\"h\":4}]}"[..], "out of range"),
+		(&b"{\"regions\":[{\"x\":1,\"y\":2,\"w\":3}]}"[..], "Need fields x, y, w and h"),
+		(&b"{\"cooldown\":0}"[..], "out of range"),
+		(&b"{} {}"[..], "Expected end of JSON"),
+	].iter() {
+		let msg = SceneConfig::parse(config).unwrap_err();
+		assert!(msg.contains(error), "{} does not contain {}", msg, error);
+	}
+}
+
+#[test]
+fn cursor_roundtrip()
+{
+	for &(ts, recordid) in [(0, 0), (1500000000000, 42), (-1, 1), (i64::min_value(), i32::max_value()),
+		(i64::max_value(), -5)].iter() {
+		let cursor = encode_cursor(ts, recordid);
+		assert_eq!(cursor.len(), 24);
+		assert_eq!(decode_cursor(&cursor).unwrap(), (ts, recordid));
+	}
+	assert_eq!(decode_cursor("0000015d3ef798000000002A").unwrap(), (1500000000000, 42));
+}
+
+#[test]
+fn cursor_invalid()
+{
+	for cursor in ["", "00000000000000000000000", "0000000000000000000000000", "00000000000000000000000g",
+		"+000000000000000+0000000", "-000000000000001-0000001", "000000000000000\u{e9}0000000"].iter() {
+		assert!(decode_cursor(cursor).is_err(), "{} accepted", cursor);
+	}
+}
+
+//Numbers of the $n placeholders in SQL, in order.
+fn sql_placeholders(sql: &str) -> Vec<usize>
+{
+	let mut found = Vec::new();
+	let mut chars = sql.chars().peekable();
+	while let Some(c) = chars.next() {
+		if c != '$' { continue; }
+		let mut n = 0;
+		while let Some(d) = chars.peek().and_then(|d|d.to_digit(10)) {
+			n = n * 10 + d as usize;
+			chars.next();
+		}
+		found.push(n);
+	}
+	found
+}
+
+#[test]
+fn event_filter_placeholders()
+{
+	let filter = EventFilter::default();
+	for &first in [1, 2, 7, 9].iter() {
+		let mut used = sql_placeholders(&EventFilter::condition(first));
+		used.sort();
+		used.dedup();
+		assert_eq!(used, (first..first + filter.params().len()).collect::<Vec<_>>());
+	}
+	let condition = EventFilter::condition(7);
+	assert!(condition.contains("$7::text[] IS NULL OR d.username=ANY($7)"));
+	assert!(condition.contains("$8::integer[] IS NULL OR (d.color & 16777215)=ANY($8)"));
+	assert!(condition.contains("d.x>=GREATEST($9::integer,0)-s.xorigin::bigint AND \
+		d.y>=GREATEST($10::integer,0)-s.yorigin::bigint AND d.x<=$11::integer-s.xorigin::bigint AND \
+		d.y<=$12::integer-s.yorigin::bigint"));
+}
+
+#[test]
+fn scaled_view_bounds()
+{
+	let mut img = MemoryImageState::new(5, 3);
+	for y in 0..3 { for x in 0..5 { img.write_pixel(x, y, 1, y * 16 + x); } }
+	assert!(ScaledView::new(&img, 5, 0, 1, 1, 1).is_none());
+	assert!(ScaledView::new(&img, 0, 3, 1, 1, 1).is_none());
+	assert!(ScaledView::new(&img, 0, 0, 0, 1, 1).is_none());
+	assert!(ScaledView::new(&img, 0, 0, 1, 0, 1).is_none());
+	assert!(ScaledView::new(&img, 0, 0, 1, 1, 0).is_none());
+	//Clipped to the image, then scaled.
+	let view = ScaledView::new(&img, 3, 1, 10, 10, 2).unwrap();
+	assert_eq!(view.get_size(), (4, 4));
+	let pixels = image_pixels(&view);
+	for y in 0..4 {
+		for x in 0..4 {
+			let c = (1 + y / 2) * 16 + 3 + x / 2;
+			assert_eq!(&pixels[4 * (4 * y + x)..][..4], &[0, (c >> 8) as u8, c as u8, 255]);
+		}
+	}
+	//Reading from the middle of row, and past its end.
+	let mut buf = [7;8];
+	view.read_row_at(0, 3, &mut buf);
+	assert_eq!(buf, [0, 0, 20, 255, 7, 7, 7, 7]);
+	let mut buf = [7;4];
+	view.read_row_at(0, 4, &mut buf);
+	assert_eq!(buf, [7;4]);
+}
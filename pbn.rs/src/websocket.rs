@@ -0,0 +1,370 @@
+use ::{db_connect, websocket_address, websocket_connections, websocket_workers};
+use ::authentication::AuthenticationInfo;
+use ::json::{JsonStream, JsonToken, escape_json_string};
+use ::scene::Scene;
+use ::scene_endpoint::{EventInfo, EventWriter, format_row, parse_one_event};
+use ::scene_events::{events_after, listen_scene, notify_scene, scene_channel, unlisten_scene};
+use fallible_iterator::FallibleIterator;
+use postgres::Connection;
+use rocket::http::RawStr;
+use rocket::request::FromParam;
+use std::collections::{HashMap, HashSet};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+use std::str::from_utf8;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender as ChannelSender, SyncSender, TrySendError};
+use std::thread::spawn;
+use std::time::Duration;
+use ws::{Builder, Handler, Handshake, Message, Request, Response, Sender, Settings, CloseCode,
+	Result as WsResult};
+
+//How often (in milliseconds) the forwarding thread checks for new subscriptions while waiting for events.
+const COMMAND_POLL_INTERVAL: u64 = 100;
+//Jobs that can be queued for each worker before sockets get told the server is busy.
+const JOB_QUEUE: usize = 64;
+
+//Database work for sockets. This is done in worker threads so the event loop never waits for the database.
+enum Job
+{
+	//Check the credentials and start forwarding events to socket.
+	Authenticate(Sender, Scene, AuthenticationInfo),
+	//Write event sent on socket.
+	Write(Sender, EventInfo),
+	//Socket is gone.
+	Close(u32),
+}
+
+//Requests to the forwarding thread.
+enum Subscription
+{
+	//Send events in scene after record number to socket.
+	Add(Sender, Scene, i32),
+	//Stop sending to socket with this connection id.
+	Remove(u32),
+}
+
+struct Subscriber
+{
+	out: Sender,
+	last: i32,
+	gone: bool,
+}
+
+fn reject(code: u16, reason: &'static str) -> WsResult<Response>
+{
+	Ok(Response::new(code, reason, format!("{}\n", reason).into_bytes()))
+}
+
+fn header_string(req: &Request, name: &str) -> Option<String>
+{
+	req.header(name).and_then(|x|from_utf8(x).ok()).map(|x|x.to_owned())
+}
+
+fn send_error(out: &Sender, msg: &str) -> WsResult<()>
+{
+	out.send(format!(r#"{{"error":"{}"}}"#, escape_json_string(msg)))
+}
+
+//Each frame carries one event object.
+fn parse_frame(msg: &str) -> Result<EventInfo, String>
+{
+	let mut data = msg.as_bytes();
+	let mut stream = JsonStream::new(&mut data);
+	stream.expect_object().map_err(|x|format!("Expecting start of event object: {}", x))?;
+	let ev = parse_one_event(&mut stream)?;
+	stream.expect_end_of_json().map_err(|x|format!("Expected end of JSON: {}", x))?;
+	Ok(ev)
+}
+
+//Authentication frame is an object with optional 'api-origin' and 'api-key'.
+fn parse_auth_frame(msg: &str) -> Result<(Option<String>, Option<String>), String>
+{
+	let mut aorigin = None;
+	let mut key = None;
+	let mut data = msg.as_bytes();
+	let mut stream = JsonStream::new(&mut data);
+	stream.expect_object().map_err(|x|format!("Expecting start of authentication object: {}", x))?;
+	stream.do_object(|stream, name|{
+		let value = match stream.next(&|x|format!("Error reading '{}': {}", name, x))? {
+			JsonToken::String(value) => value,
+			x => return Err(format!("Expected string for '{}', got {:?}", name, x))
+		};
+		match &name[..] {
+			"api-origin" => aorigin = Some(value),
+			"api-key" => key = Some(value),
+			_ => return Err(format!("Unrecognized authentication key '{}'", name))
+		}
+		Ok(())
+	}, &|x|format!("Error in authentication object: {}", x))?;
+	stream.expect_end_of_json().map_err(|x|format!("Expected end of JSON: {}", x))?;
+	Ok((aorigin, key))
+}
+
+//Send new events in scene to subscribers, dropping the ones whose socket is gone.
+fn forward_scene(conn: &Connection, scene: Scene, subscribers: &mut Vec<Subscriber>)
+{
+	loop {
+		let first = match subscribers.iter().map(|x|x.last).min() { Some(x) => x, None => return };
+		let events = events_after(conn, scene, first);
+		if events.len() == 0 { return; }
+		for (recordid, ev) in events {
+			let mut msg = String::new();
+			format_row(&mut msg, &ev);
+			for sub in subscribers.iter_mut().filter(|x|x.last < recordid) {
+				sub.gone |= sub.out.send(msg.clone()).is_err();
+				sub.last = recordid;
+			}
+		}
+		subscribers.retain(|x|!x.gone);
+	}
+}
+
+//Forward events to all sockets. One database connection listens to every scene that has subscribers.
+fn forward_events(requests: Receiver<Subscription>)
+{
+	let conn = db_connect();
+	let mut scenes: HashMap<Scene, Vec<Subscriber>> = HashMap::new();
+	loop {
+		let mut pending = Vec::new();
+		//Nothing to wait for without subscribers.
+		if scenes.len() == 0 {
+			match requests.recv() { Ok(x) => pending.push(x), Err(_) => return }
+		}
+		while let Ok(x) = requests.try_recv() { pending.push(x); }
+		let mut dirty = HashSet::new();
+		for request in pending {
+			match request {
+				Subscription::Add(out, scene, last) => {
+					if !scenes.contains_key(&scene) { listen_scene(&conn, scene); }
+					scenes.entry(scene).or_insert_with(Vec::new).push(Subscriber{out: out, last: last,
+						gone: false});
+					//Send anything written between authentication and now.
+					dirty.insert(scene);
+				},
+				Subscription::Remove(id) => for subscribers in scenes.values_mut() {
+					subscribers.retain(|x|x.out.connection_id() != id);
+				},
+			}
+		}
+		let channels: HashMap<String, Scene> = scenes.keys().map(|&x|(scene_channel(x), x)).collect();
+		let mut notified = conn.notifications().timeout_iter(Duration::from_millis(COMMAND_POLL_INTERVAL)).
+			next().ok().and_then(|x|x);
+		while let Some(n) = notified {
+			if let Some(&scene) = channels.get(&n.channel) { dirty.insert(scene); }
+			notified = conn.notifications().iter().next().ok().and_then(|x|x);
+		}
+		for scene in dirty {
+			if let Some(subscribers) = scenes.get_mut(&scene) { forward_scene(&conn, scene, subscribers); }
+		}
+		let empty: Vec<Scene> = scenes.iter().filter(|x|x.1.len() == 0).map(|x|*x.0).collect();
+		for scene in empty {
+			unlisten_scene(&conn, scene);
+			scenes.remove(&scene);
+		}
+	}
+}
+
+impl Job
+{
+	fn connection_id(&self) -> u32
+	{
+		match self {
+			&Job::Authenticate(ref out, _, _) => out.connection_id(),
+			&Job::Write(ref out, _) => out.connection_id(),
+			&Job::Close(id) => id,
+		}
+	}
+}
+
+fn run_job(conn: &mut Connection, writers: &mut HashMap<u32, (Scene, EventWriter)>,
+	subscriptions: &ChannelSender<Subscription>, job: Job)
+{
+	match job {
+		Job::Authenticate(out, scene, auth) => {
+			let appid = match auth.check_write(conn, scene) {
+				Ok(x) => x,
+				Err(badorigin) => {
+					send_error(&out, if badorigin { "Invalid origin" } else { "Scene not found" }).ok();
+					out.close(CloseCode::Policy).ok();
+					return;
+				}
+			};
+			//Read the starting point here, so events sent right after authenticating are not missed.
+			let last: i32 = conn.query("SELECT COALESCE(MAX(recordid),0) FROM scene_data WHERE sceneid=$1",
+				&[&scene]).unwrap().iter().next().unwrap().get(0);
+			writers.insert(out.connection_id(), (scene, EventWriter::new(scene, appid)));
+			subscriptions.send(Subscription::Add(out, scene, last)).ok();
+		},
+		Job::Write(out, ev) => {
+			let &mut (scene, ref mut writer) = match writers.get_mut(&out.connection_id()) {
+				Some(x) => x,
+				None => { send_error(&out, "Not authenticated").ok(); return; }
+			};
+			//The event gets echoed back by the forwarding thread.
+			conn.execute("BEGIN TRANSACTION", &[]).unwrap();
+			let result = writer.lock(conn).and_then(|_|writer.write(conn, &ev));
+			if result.is_ok() {
+				notify_scene(conn, scene);
+				conn.execute("COMMIT", &[]).unwrap();
+			} else {
+				conn.execute("ROLLBACK", &[]).unwrap();
+			}
+			if let Err(x) = result { send_error(&out, &x.message()).ok(); }
+		},
+		Job::Close(id) => {
+			writers.remove(&id);
+			subscriptions.send(Subscription::Remove(id)).ok();
+		},
+	}
+}
+
+//Process jobs for the sockets assigned to this worker. Jobs of each socket are done in order.
+fn worker(jobs: Receiver<Job>, subscriptions: ChannelSender<Subscription>)
+{
+	let mut conn = db_connect();
+	let mut writers = HashMap::new();
+	for job in jobs.iter() {
+		//A failing job must not take the other sockets of this worker down with it.
+		let id = job.connection_id();
+		if catch_unwind(AssertUnwindSafe(||run_job(&mut conn, &mut writers, &subscriptions, job))).is_err() {
+			writers.remove(&id);
+			conn = db_connect();
+		}
+	}
+}
+
+struct SceneSocket
+{
+	out: Sender,
+	workers: Rc<Vec<SyncSender<Job>>>,
+	//Filled in by handshake.
+	scene: Option<Scene>,
+	origin: Option<String>,
+	auth: Option<AuthenticationInfo>,
+	//Set once the credentials have been sent to worker. Frames after that are events.
+	authenticating: bool,
+}
+
+impl SceneSocket
+{
+	//All jobs of socket go to the same worker, so they are done in order.
+	fn queue(&self) -> &SyncSender<Job>
+	{
+		&self.workers[self.out.connection_id() as usize % self.workers.len()]
+	}
+	fn submit(&self, job: Job) -> Result<(), &'static str>
+	{
+		match self.queue().try_send(job) {
+			Ok(_) => Ok(()),
+			Err(TrySendError::Full(_)) => Err("Server busy"),
+			Err(TrySendError::Disconnected(_)) => Err("Server shutting down"),
+		}
+	}
+	fn authenticate(&mut self, auth: AuthenticationInfo) -> WsResult<()>
+	{
+		let scene = match self.scene { Some(x) => x, None => return Ok(()) };
+		self.authenticating = true;
+		if let Err(x) = self.submit(Job::Authenticate(self.out.clone(), scene, auth)) {
+			send_error(&self.out, x)?;
+			return self.out.close(CloseCode::Again);
+		}
+		Ok(())
+	}
+}
+
+impl Handler for SceneSocket
+{
+	fn on_request(&mut self, req: &Request) -> WsResult<Response>
+	{
+		//The resource is /scenes/<scene>/ws, optionally followed by query.
+		let resource = req.resource().to_owned();
+		let path = match resource.find('?') {
+			Some(pos) => &resource[..pos],
+			None => &resource[..]
+		};
+		let mut parts = path.split('/');
+		let scene = match (parts.next(), parts.next(), parts.next(), parts.next(), parts.next()) {
+			(Some(""), Some("scenes"), Some(scene), Some("ws"), None) => scene,
+			_ => return reject(404, "Not found")
+		};
+		let scene = match Scene::from_param(RawStr::from_str(scene)) {
+			Ok(x) => x,
+			Err(_) => return reject(404, "Scene not found")
+		};
+		self.scene = Some(scene);
+		//Browsers can not set headers on websockets. Without API key header, the first frame authenticates.
+		let aorigin = header_string(req, "api-origin");
+		self.origin = header_string(req, "origin");
+		if let Some(key) = header_string(req, "api-key") {
+			let origin = self.origin.clone().or(aorigin.clone());
+			self.auth = Some(AuthenticationInfo::new(origin, aorigin.is_some(), Some(key)));
+		}
+		Response::from_request(req)
+	}
+	fn on_open(&mut self, _shake: Handshake) -> WsResult<()>
+	{
+		match self.auth.take() {
+			Some(auth) => self.authenticate(auth),
+			None => Ok(())
+		}
+	}
+	fn on_message(&mut self, msg: Message) -> WsResult<()>
+	{
+		let msg = match msg {
+			Message::Text(x) => x,
+			Message::Binary(_) => return send_error(&self.out, "Expected text frame"),
+		};
+		if !self.authenticating {
+			let (aorigin, key) = match parse_auth_frame(&msg) {
+				Ok(x) => x,
+				Err(x) => {
+					send_error(&self.out, &format!("Bad authentication: {}", x))?;
+					return self.out.close(CloseCode::Policy);
+				}
+			};
+			let origin = self.origin.clone().or(aorigin.clone());
+			return self.authenticate(AuthenticationInfo::new(origin, aorigin.is_some(), key));
+		}
+		let ev = match parse_frame(&msg) {
+			Ok(x) => x,
+			Err(x) => return send_error(&self.out, &format!("Bad event: {}", x))
+		};
+		if let Err(x) = self.submit(Job::Write(self.out.clone(), ev)) { return send_error(&self.out, x); }
+		Ok(())
+	}
+}
+
+impl Drop for SceneSocket
+{
+	fn drop(&mut self)
+	{
+		//This must get through, or the worker keeps the writer forever.
+		if self.authenticating { self.queue().send(Job::Close(self.out.connection_id())).ok(); }
+	}
+}
+
+//Run the websocket server. Does not return.
+pub fn websocket_server()
+{
+	let addr = websocket_address();
+	let (subscriptions, requests) = channel();
+	spawn(move ||forward_events(requests));
+	let mut workers = Vec::new();
+	for _ in 0..websocket_workers() {
+		let (jobs, queue) = sync_channel(JOB_QUEUE);
+		let subscriptions = subscriptions.clone();
+		spawn(move ||worker(queue, subscriptions));
+		workers.push(jobs);
+	}
+	let workers = Rc::new(workers);
+	//Connections over the limit are closed right after accepting.
+	let settings = Settings{max_connections: websocket_connections(), .. Settings::default()};
+	Builder::new().with_settings(settings).build(|out|SceneSocket{
+		out: out,
+		workers: workers.clone(),
+		scene: None,
+		origin: None,
+		auth: None,
+		authenticating: false,
+	}).and_then(|x|x.listen(&addr[..])).unwrap();
+}
@@ -86,7 +86,7 @@ fn is_valid_xml_name(tag1: &str, tag2: &str, attribute: bool) -> Result<(), XmlE
 	//'xmlns:' (or being 'xmlns').
 	if ((_tag[0] ^ 88) | (_tag[1] ^ 77) | (_tag[2] ^ 76)) & 0xDF == 0 {
 		if (_tag.starts_with(b"xmlns:") || &_tag == b"xmlns\x00") && attribute {
-			()	//Ignore.
+			//Ignore.
 		} else {
 			return Err(XmlEncodeError("Reserved name"));	//Reserved by XML.
 		};
@@ -607,6 +607,9 @@ fn basic_xhtml()
 		x.text("zot");
 	});
 	x.close();
-	println!("{}", x.into_inner().unwrap());
-	assert!(false);
+	assert_eq!(x.into_inner().unwrap(), "<?xml version=\"1.0\"?>\n<html:html xmlns:html=\"http://www.w3.org/1999/\
+		xhtml\"><html:head><html:title>foobar</html:title></html:head><html:body><html:p>qux<html:p/><html:p \
+		id=\"foo\" class=\"bar\"></html:p><html:zzz data-src=\"foo\"/><html:www data-zot=\"bar\"/><yyy \
+		data-foo=\"foo&lt;&gt;&amp;&quot;zot&apos;bar\"/><html:www2 id=\"zot\" class=\"foobar\"/>zot</html:p>\
+		</html:body></html:html>");
 }
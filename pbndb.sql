@@ -82,6 +82,19 @@ ALTER TABLE applications_appid_seq OWNER TO postgres;
 ALTER SEQUENCE applications_appid_seq OWNED BY applications.appid;
 
 
+--
+-- Name: scene_cooldowns; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE scene_cooldowns (
+    sceneid integer NOT NULL,
+    username text NOT NULL,
+    last bigint NOT NULL
+);
+
+
+ALTER TABLE scene_cooldowns OWNER TO postgres;
+
 --
 -- Name: scene_data; Type: TABLE; Schema: public; Owner: postgres
 --
@@ -120,6 +133,47 @@ ALTER TABLE scene_data_recordid_seq OWNER TO postgres;
 ALTER SEQUENCE scene_data_recordid_seq OWNED BY scene_data.recordid;
 
 
+--
+-- Name: scene_protected; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE scene_protected (
+    regionid integer NOT NULL,
+    sceneid integer NOT NULL,
+    x integer NOT NULL,
+    y integer NOT NULL,
+    w integer NOT NULL,
+    h integer NOT NULL,
+    appids integer[] NOT NULL,
+    usernames text[] NOT NULL
+);
+
+
+ALTER TABLE scene_protected OWNER TO postgres;
+
+
+--
+-- Name: scene_protected_regionid_seq; Type: SEQUENCE; Schema: public; Owner: postgres
+--
+
+CREATE SEQUENCE scene_protected_regionid_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+ALTER TABLE scene_protected_regionid_seq OWNER TO postgres;
+
+
+--
+-- Name: scene_protected_regionid_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: postgres
+--
+
+ALTER SEQUENCE scene_protected_regionid_seq OWNED BY scene_protected.regionid;
+
+
 --
 -- Name: scenes; Type: TABLE; Schema: public; Owner: postgres
 --
@@ -128,7 +182,15 @@ CREATE TABLE scenes (
     sceneid integer NOT NULL,
     name text,
     width integer,
-    height integer
+    height integer,
+    palette integer[],
+    description text,
+    public boolean DEFAULT false NOT NULL,
+    modified bigint,
+    modifiedby text,
+    owner integer,
+    xorigin integer DEFAULT 0 NOT NULL,
+    yorigin integer DEFAULT 0 NOT NULL
 );
 
 
@@ -155,6 +217,27 @@ ALTER TABLE scenes_sceneid_seq OWNER TO postgres;
 ALTER SEQUENCE scenes_sceneid_seq OWNED BY scenes.sceneid;
 
 
+--
+-- Name: scene_view; Type: VIEW; Schema: public; Owner: postgres
+--
+
+CREATE VIEW scene_view AS
+ SELECT d.recordid,
+    d.sceneid,
+    d."timestamp",
+    d.username,
+    d.color,
+    (LEAST(GREATEST(((d.x)::bigint + s.xorigin), (-1)::bigint), (2147483647)::bigint))::integer AS x,
+    (LEAST(GREATEST(((d.y)::bigint + s.yorigin), (-1)::bigint), (2147483647)::bigint))::integer AS y
+   FROM (scene_data d
+     JOIN scenes s ON ((d.sceneid = s.sceneid)))
+  WHERE ((((d.x)::bigint + s.xorigin) >= 0) AND (((d.x)::bigint + s.xorigin) <= 2147483647) AND
+    (((d.y)::bigint + s.yorigin) >= 0) AND (((d.y)::bigint + s.yorigin) <= 2147483647));
+
+
+ALTER TABLE scene_view OWNER TO postgres;
+
+
 --
 -- Name: appid; Type: DEFAULT; Schema: public; Owner: postgres
 --
@@ -169,6 +252,13 @@ ALTER TABLE ONLY applications ALTER COLUMN appid SET DEFAULT nextval('applicatio
 ALTER TABLE ONLY scene_data ALTER COLUMN recordid SET DEFAULT nextval('scene_data_recordid_seq'::regclass);
 
 
+--
+-- Name: regionid; Type: DEFAULT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY scene_protected ALTER COLUMN regionid SET DEFAULT nextval('scene_protected_regionid_seq'::regclass);
+
+
 --
 -- Name: sceneid; Type: DEFAULT; Schema: public; Owner: postgres
 --
@@ -188,6 +278,12 @@ SELECT pg_catalog.setval('applications_appid_seq', 1, true);
 
 SELECT pg_catalog.setval('scene_data_recordid_seq', 1, true);
 
+--
+-- Name: scene_protected_regionid_seq; Type: SEQUENCE SET; Schema: public; Owner: postgres
+--
+
+SELECT pg_catalog.setval('scene_protected_regionid_seq', 1, true);
+
 --
 -- Name: scenes_sceneid_seq; Type: SEQUENCE SET; Schema: public; Owner: postgres
 --
@@ -211,6 +307,14 @@ ALTER TABLE ONLY applications
     ADD CONSTRAINT applications_pkey PRIMARY KEY (appid);
 
 
+--
+-- Name: scene_cooldowns_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY scene_cooldowns
+    ADD CONSTRAINT scene_cooldowns_pkey PRIMARY KEY (sceneid, username);
+
+
 --
 -- Name: scene_data_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
 --
@@ -219,6 +323,14 @@ ALTER TABLE ONLY scene_data
     ADD CONSTRAINT scene_data_pkey PRIMARY KEY (recordid);
 
 
+--
+-- Name: scene_protected_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY scene_protected
+    ADD CONSTRAINT scene_protected_pkey PRIMARY KEY (regionid);
+
+
 --
 -- Name: scenes_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
 --
@@ -257,6 +369,13 @@ ALTER TABLE scene_data CLUSTER ON scene_data_sceneid;
 CREATE INDEX scene_data_sceneid_ts2 ON scene_data USING btree (sceneid, "timestamp");
 
 
+--
+-- Name: scene_data_sceneid_xy; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX scene_data_sceneid_xy ON scene_data USING btree (sceneid, x, y);
+
+
 --
 -- Name: application_scene_appid_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
 --
@@ -273,6 +392,22 @@ ALTER TABLE ONLY application_scene
     ADD CONSTRAINT application_scene_senecid_fkey FOREIGN KEY (sceneid) REFERENCES scenes(sceneid) ON DELETE CASCADE;
 
 
+--
+-- Name: scenes_owner_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY scenes
+    ADD CONSTRAINT scenes_owner_fkey FOREIGN KEY (owner) REFERENCES applications(appid) ON DELETE SET NULL;
+
+
+--
+-- Name: scene_cooldowns_sceneid_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY scene_cooldowns
+    ADD CONSTRAINT scene_cooldowns_sceneid_fkey FOREIGN KEY (sceneid) REFERENCES scenes(sceneid) ON DELETE CASCADE;
+
+
 --
 -- Name: scene_data_sceneid_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
 --
@@ -281,6 +416,14 @@ ALTER TABLE ONLY scene_data
     ADD CONSTRAINT scene_data_sceneid_fkey FOREIGN KEY (sceneid) REFERENCES scenes(sceneid) ON DELETE CASCADE;
 
 
+--
+-- Name: scene_protected_sceneid_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY scene_protected
+    ADD CONSTRAINT scene_protected_sceneid_fkey FOREIGN KEY (sceneid) REFERENCES scenes(sceneid) ON DELETE CASCADE;
+
+
 --
 -- Name: public; Type: ACL; Schema: -; Owner: postgres
 --
@@ -311,6 +454,16 @@ GRANT ALL ON TABLE applications TO postgres;
 GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE applications TO pbn;
 
 
+--
+-- Name: scene_cooldowns; Type: ACL; Schema: public; Owner: postgres
+--
+
+REVOKE ALL ON TABLE scene_cooldowns FROM PUBLIC;
+REVOKE ALL ON TABLE scene_cooldowns FROM postgres;
+GRANT ALL ON TABLE scene_cooldowns TO postgres;
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE scene_cooldowns TO pbn;
+
+
 --
 -- Name: scene_data; Type: ACL; Schema: public; Owner: postgres
 --
@@ -331,6 +484,26 @@ GRANT ALL ON SEQUENCE scene_data_recordid_seq TO postgres;
 GRANT ALL ON SEQUENCE scene_data_recordid_seq TO pbn;
 
 
+--
+-- Name: scene_protected; Type: ACL; Schema: public; Owner: postgres
+--
+
+REVOKE ALL ON TABLE scene_protected FROM PUBLIC;
+REVOKE ALL ON TABLE scene_protected FROM postgres;
+GRANT ALL ON TABLE scene_protected TO postgres;
+GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE scene_protected TO pbn;
+
+
+--
+-- Name: scene_protected_regionid_seq; Type: ACL; Schema: public; Owner: postgres
+--
+
+REVOKE ALL ON SEQUENCE scene_protected_regionid_seq FROM PUBLIC;
+REVOKE ALL ON SEQUENCE scene_protected_regionid_seq FROM postgres;
+GRANT ALL ON SEQUENCE scene_protected_regionid_seq TO postgres;
+GRANT ALL ON SEQUENCE scene_protected_regionid_seq TO pbn;
+
+
 --
 -- Name: scenes; Type: ACL; Schema: public; Owner: postgres
 --
@@ -341,6 +514,16 @@ GRANT ALL ON TABLE scenes TO postgres;
 GRANT SELECT,INSERT,DELETE,UPDATE ON TABLE scenes TO pbn;
 
 
+--
+-- Name: scene_view; Type: ACL; Schema: public; Owner: postgres
+--
+
+REVOKE ALL ON TABLE scene_view FROM PUBLIC;
+REVOKE ALL ON TABLE scene_view FROM postgres;
+GRANT ALL ON TABLE scene_view TO postgres;
+GRANT SELECT ON TABLE scene_view TO pbn;
+
+
 --
 -- Name: scenes_sceneid_seq; Type: ACL; Schema: public; Owner: postgres
 --